@@ -0,0 +1,91 @@
+use std::path::{Path, PathBuf};
+
+use teloxide::{
+    net::Download,
+    prelude::*,
+    types::InputFile,
+    RequestError,
+};
+
+use crate::{
+    error::BotResult,
+    storage::{Attachment, AttachmentKind, StorageError},
+};
+
+/// The file of a photo, document or voice message; for a photo, its
+/// largest size.
+pub fn attachment_of(msg: &Message) -> Option<Attachment> {
+    let (kind, file_id) = if let Some(photo) = msg.photo() {
+        (AttachmentKind::Photo, &photo.last()?.file.id)
+    } else if let Some(document) = msg.document() {
+        (AttachmentKind::Document, &document.file.id)
+    } else if let Some(voice) = msg.voice() {
+        (AttachmentKind::Voice, &voice.file.id)
+    } else {
+        return None;
+    };
+
+    Some(Attachment { kind, file_id: file_id.clone(), path: None })
+}
+
+/// How the note card names an attachment.
+pub fn kind_name(kind: AttachmentKind) -> &'static str {
+    match kind {
+        AttachmentKind::Photo => "фото",
+        AttachmentKind::Document => "документ",
+        AttachmentKind::Voice => "голосовое сообщение",
+    }
+}
+
+/// Saves a copy of the attachment into `folder/<chat id>/` and returns its path.
+pub async fn download(bot: &Bot, chat_id: ChatId, attachment: &Attachment, folder: &Path) -> BotResult<PathBuf> {
+    let file = bot.get_file(attachment.file_id.clone()).await?;
+
+    let folder = folder.join(chat_id.to_string());
+    tokio::fs::create_dir_all(&folder).await.map_err(StorageError::from)?;
+    let mut path = folder.join(&file.meta.unique_id);
+    if let Some(extension) = Path::new(&file.path).extension() {
+        path.set_extension(extension);
+    }
+
+    let mut destination = tokio::fs::File::create(&path).await.map_err(StorageError::from)?;
+    if let Err(error) = bot.download_file(&file.path, &mut destination).await {
+        let _ = tokio::fs::remove_file(&path).await;
+        return Err(RequestError::from(error).into());
+    }
+
+    Ok(path)
+}
+
+/// Sends the attachment again, from the saved copy if Telegram no longer
+/// knows its `file_id`.
+pub async fn send_attachment(bot: &Bot, chat_id: ChatId, attachment: &Attachment) -> BotResult {
+    let sent = send_file(bot, chat_id, attachment.kind, InputFile::file_id(attachment.file_id.clone())).await;
+
+    match (sent, &attachment.path) {
+        (Err(error), Some(path)) if path.is_file() => {
+            log::warn!("cannot resend file {}: {}; sending the saved copy", attachment.file_id, error);
+            send_file(bot, chat_id, attachment.kind, InputFile::file(path)).await
+        },
+        (sent, _) => sent,
+    }
+}
+
+async fn send_file(bot: &Bot, chat_id: ChatId, kind: AttachmentKind, file: InputFile) -> BotResult {
+    match kind {
+        AttachmentKind::Photo => bot.send_photo(chat_id, file).await?,
+        AttachmentKind::Document => bot.send_document(chat_id, file).await?,
+        AttachmentKind::Voice => bot.send_voice(chat_id, file).await?,
+    };
+
+    Ok(())
+}
+
+/// Removes the saved copy of an attachment whose note is deleted.
+pub async fn remove_copy(attachment: &Attachment) {
+    if let Some(path) = &attachment.path {
+        if let Err(error) = tokio::fs::remove_file(path).await {
+            log::warn!("cannot remove {}: {}", path.display(), error);
+        }
+    }
+}
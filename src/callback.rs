@@ -0,0 +1,126 @@
+use std::{fmt, str::FromStr};
+
+use crate::{functions::TagOrder, storage::NoteId};
+
+/// Payload of an inline keyboard button. Telegram limits callback data to
+/// 64 bytes, so it is encoded as a one-letter action followed by the note id
+/// (or the page number, or the tag index), then numbers after colons: the
+/// parent tag and a checksum of its name for the tag browser, a checksum of
+/// the tag name for the tag buttons, the minutes for the snooze buttons and
+/// the item and a checksum of its label for the checklist buttons.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Callback {
+    Open(NoteId),
+    Delete(NoteId),
+    /// Asks for a new text.
+    Edit(NoteId),
+    EditTitle(NoteId),
+    EditTags(NoteId),
+    /// Asks for text to add at the end.
+    Append(NoteId),
+    /// Asks when to remind of the note.
+    Remind(NoteId),
+    Unremind(NoteId),
+    /// Puts off the reminder of a note that just went off.
+    Snooze { note: NoteId, minutes: usize },
+    /// Checks or unchecks a checklist item, counted from the top of the note;
+    /// `hash` is the [`checksum`] of its label.
+    Toggle { note: NoteId, item: usize, hash: u16 },
+    /// Removes the checked items of a checklist.
+    ClearDone(NoteId),
+    /// Shows another page of the chat's last search results.
+    Page(usize),
+    /// Shows a page of the tag browser, inside `parent` if there is one:
+    /// its number, as for [`Callback::Tag`], and the [`checksum`] of its name.
+    Tags { parent: Option<(usize, u16)>, page: usize, order: TagOrder },
+    /// Lists the notes under a tag; tags are numbered by name, as
+    /// [`NoteStore::tags`](crate::storage::NoteStore::tags) returns them,
+    /// and `hash` is the [`checksum`] of the name the number stood for.
+    Tag { index: usize, hash: u16 },
+}
+
+impl Callback {
+    pub fn tag(index: usize, name: &str) -> Self {
+        Callback::Tag { index, hash: checksum(name) }
+    }
+
+    pub fn toggle(note: NoteId, item: usize, label: &str) -> Self {
+        Callback::Toggle { note, item, hash: checksum(label) }
+    }
+}
+
+/// A short checksum of a tag name or a checklist label that stays the same
+/// across restarts, so that a button addressing it by position can tell that
+/// the list it was made from has changed.
+pub fn checksum(text: &str) -> u16 {
+    let hash = text.bytes().fold(0x811c_9dc5_u32, |hash, byte| (hash ^ byte as u32).wrapping_mul(0x0100_0193));
+
+    (hash >> 16) as u16 ^ hash as u16
+}
+
+impl fmt::Display for Callback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Callback::Open(id) => write!(f, "o{}", id),
+            Callback::Delete(id) => write!(f, "d{}", id),
+            Callback::Edit(id) => write!(f, "e{}", id),
+            Callback::EditTitle(id) => write!(f, "r{}", id),
+            Callback::EditTags(id) => write!(f, "m{}", id),
+            Callback::Append(id) => write!(f, "a{}", id),
+            Callback::Remind(id) => write!(f, "w{}", id),
+            Callback::Unremind(id) => write!(f, "u{}", id),
+            Callback::Snooze { note, minutes } => write!(f, "s{}:{}", note, minutes),
+            Callback::Toggle { note, item, hash } => write!(f, "x{}:{}:{}", note, item, hash),
+            Callback::ClearDone(id) => write!(f, "c{}", id),
+            Callback::Page(page) => write!(f, "p{}", page),
+            Callback::Tags { parent, page, order } => {
+                let action = match order {
+                    TagOrder::Count => 't',
+                    TagOrder::Name => 'n',
+                };
+                match parent {
+                    Some((parent, hash)) => write!(f, "{}{}:{}:{}", action, page, parent, hash),
+                    None => write!(f, "{}{}", action, page),
+                }
+            },
+            Callback::Tag { index, hash } => write!(f, "g{}:{}", index, hash),
+        }
+    }
+}
+
+impl FromStr for Callback {
+    type Err = String;
+
+    fn from_str(data: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid callback data: {}", data);
+
+        let mut chars = data.chars();
+        let action = chars.next().ok_or("empty callback data")?;
+        let mut parts = chars.as_str().split(':');
+        let id: NoteId = parts.next().unwrap_or_default().parse().map_err(|_| invalid())?;
+        let suffix = parts.map(str::parse::<usize>).collect::<Result<Vec<_>, _>>().map_err(|_| invalid())?;
+        let index = || usize::try_from(id).map_err(|_| invalid());
+        let hash = |value: usize| u16::try_from(value).map_err(|_| invalid());
+
+        match (action, suffix.as_slice()) {
+            ('o', []) => Ok(Callback::Open(id)),
+            ('d', []) => Ok(Callback::Delete(id)),
+            ('e', []) => Ok(Callback::Edit(id)),
+            ('r', []) => Ok(Callback::EditTitle(id)),
+            ('m', []) => Ok(Callback::EditTags(id)),
+            ('a', []) => Ok(Callback::Append(id)),
+            ('w', []) => Ok(Callback::Remind(id)),
+            ('u', []) => Ok(Callback::Unremind(id)),
+            ('s', &[minutes]) => Ok(Callback::Snooze { note: id, minutes }),
+            ('x', &[item, label]) => Ok(Callback::Toggle { note: id, item, hash: hash(label)? }),
+            ('c', []) => Ok(Callback::ClearDone(id)),
+            ('p', []) => Ok(Callback::Page(index()?)),
+            ('t', []) => Ok(Callback::Tags { parent: None, page: index()?, order: TagOrder::Count }),
+            ('t', &[parent, name]) => Ok(Callback::Tags { parent: Some((parent, hash(name)?)), page: index()?, order: TagOrder::Count }),
+            ('n', []) => Ok(Callback::Tags { parent: None, page: index()?, order: TagOrder::Name }),
+            ('n', &[parent, name]) => Ok(Callback::Tags { parent: Some((parent, hash(name)?)), page: index()?, order: TagOrder::Name }),
+            ('g', &[name]) => Ok(Callback::Tag { index: index()?, hash: hash(name)? }),
+            _ => Err(invalid()),
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use std::ops::Range;
+
+use teloxide::types::MessageEntity;
+
+use crate::entities::{entities_in, shift_entities, utf16_len};
+
+const UNCHECKED: &str = "- [ ] ";
+const CHECKED: [&str; 2] = ["- [x] ", "- [X] "];
+
+/// A `- [ ] ...` or `- [x] ...` line of a note.
+#[derive(Debug, PartialEq)]
+pub struct Item<'a> {
+    pub done: bool,
+    pub label: &'a str,
+    /// Bytes of the line, without its line break.
+    line: Range<usize>,
+    /// Byte offset of the space or `x` between the brackets.
+    mark: usize,
+}
+
+/// The checklist items of `text`, in order.
+pub fn items(text: &str) -> Vec<Item<'_>> {
+    let mut items = Vec::new();
+    let mut start = 0;
+
+    for line in text.split('\n') {
+        let indent = line.len() - line.trim_start().len();
+        let rest = &line[indent..];
+        let done = if rest.starts_with(UNCHECKED) {
+            Some(false)
+        } else if CHECKED.iter().any(|checked| rest.starts_with(checked)) {
+            Some(true)
+        } else {
+            None
+        };
+
+        if let Some(done) = done {
+            let label = rest[UNCHECKED.len()..].trim();
+            if !label.is_empty() {
+                items.push(Item { done, label, line: start..start + line.len(), mark: start + indent + "- [".len() });
+            }
+        }
+        start += line.len() + 1;
+    }
+
+    items
+}
+
+/// `text` with the item number `index` checked or unchecked, or `None` if
+/// there is no such item. The mark keeps its length, so formatting stays put.
+pub fn toggle(text: &str, index: usize) -> Option<String> {
+    let item = items(text).into_iter().nth(index)?;
+
+    let mut toggled = text.to_string();
+    toggled.replace_range(item.mark..item.mark + 1, if item.done { " " } else { "x" });
+
+    Some(toggled)
+}
+
+/// `text` without its checked items, with `entities` moved to match.
+pub fn clear_done(text: &str, entities: &[MessageEntity]) -> (String, Vec<MessageEntity>) {
+    let mut kept = Vec::new();
+    let mut start = 0;
+    for item in items(text).into_iter().filter(|item| item.done) {
+        kept.push(start..item.line.start);
+        start = (item.line.end + 1).min(text.len());
+    }
+    kept.push(start..text.len());
+
+    let mut cleared = String::with_capacity(text.len());
+    let mut cleared_entities = Vec::new();
+    for range in kept.into_iter().filter(|range| !range.is_empty()) {
+        cleared_entities.extend(shift_entities(entities_in(entities, text, range.clone()), utf16_len(&cleared)));
+        cleared.push_str(&text[range]);
+    }
+
+    // Dropping the last line leaves the line break before it.
+    if cleared.ends_with('\n') && !text.ends_with('\n') {
+        cleared.pop();
+        cleared_entities = entities_in(&cleared_entities, &cleared, 0..cleared.len());
+    }
+
+    (cleared, cleared_entities)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIST: &str = "Купить:\n- [ ] молоко\n  - [x] хлеб\n- [ ]\n- [X] сыр";
+
+    #[test]
+    fn items_are_found_and_toggled() {
+        let items = items(LIST);
+        assert_eq!(items.iter().map(|item| (item.label, item.done)).collect::<Vec<_>>(), vec![("молоко", false), ("хлеб", true), ("сыр", true)]);
+
+        assert_eq!(toggle(LIST, 0).unwrap(), "Купить:\n- [x] молоко\n  - [x] хлеб\n- [ ]\n- [X] сыр");
+        assert_eq!(toggle(LIST, 2).unwrap(), "Купить:\n- [ ] молоко\n  - [x] хлеб\n- [ ]\n- [ ] сыр");
+        assert_eq!(toggle(LIST, 3), None);
+    }
+
+    #[test]
+    fn done_items_are_cleared_with_their_formatting() {
+        let entities = vec![MessageEntity::bold(0, 6), MessageEntity::italic(14, 6), MessageEntity::code(29, 4)];
+
+        let (text, entities) = clear_done(LIST, &entities);
+        assert_eq!(text, "Купить:\n- [ ] молоко\n- [ ]");
+        assert_eq!(entities, vec![MessageEntity::bold(0, 6), MessageEntity::italic(14, 6)]);
+
+        assert_eq!(clear_done("- [x] всё", &[]), (String::new(), vec![]));
+    }
+}
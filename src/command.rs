@@ -0,0 +1,74 @@
+use std::borrow::Cow;
+
+use teloxide::utils::command::{BotCommands, ParseError};
+
+#[derive(BotCommands, Clone, Debug, PartialEq)]
+#[command(rename_rule = "lowercase", description = "Доступные команды:")]
+pub enum Command {
+    #[command(description = "приветствие и краткая справка")]
+    Start,
+    #[command(description = "синтаксис заметок и список команд")]
+    Help,
+    #[command(description = "создать заметку: /new, затем #тег, заголовок и текст с новых строк")]
+    New(String),
+    #[command(description = "найти заметки: слова, AND/OR/NOT, поля title:, body:, tag:, before:, after:")]
+    Find(String),
+    #[command(description = "показать все теги с числом заметок; /tags имя — по алфавиту")]
+    Tags(String),
+    #[command(description = "переименовать тег вместе с вложенными: /renametag #старый #новый")]
+    RenameTag(String),
+    #[command(description = "перенести заметки одного тега в другой: /mergetag #откуда #куда")]
+    MergeTag(String),
+    #[command(description = "показать заметки с тегом или все заметки")]
+    List(String),
+    #[command(description = "удалить заметку по её ID")]
+    Delete(String),
+    #[command(description = "изменить текст заметки по её ID")]
+    Edit(String),
+    #[command(description = "дописать текст в конец заметки по её ID")]
+    Append(String),
+    #[command(description = "напомнить о заметке: /remind ID завтра 9:00")]
+    Remind(String),
+    #[command(description = "отменить изменение заметки")]
+    Cancel,
+}
+
+/// Parses `text` as a bot command, or returns `None` if it is free text.
+///
+/// teloxide separates the command from its arguments by a space only, so
+/// `/new` followed directly by a line break gets a space inserted first.
+pub fn parse_command(text: &str, bot_name: &str) -> Option<Result<Command, ParseError>> {
+    if !text.starts_with('/') {
+        return None;
+    }
+
+    let text = match text.find(char::is_whitespace) {
+        Some(i) if text[i..].starts_with('\n') => Cow::Owned(format!("{} {}", &text[..i], &text[i..])),
+        _ => Cow::Borrowed(text),
+    };
+
+    Some(Command::parse(&text, bot_name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn note_may_start_on_the_next_line() {
+        assert_eq!(
+            parse_command("/new\n#игры\nИгра на вечер\nСкайрим", "notebot").unwrap().unwrap(),
+            Command::New("\n#игры\nИгра на вечер\nСкайрим".to_string())
+        );
+        assert_eq!(
+            parse_command("/find@notebot скайрим", "notebot").unwrap().unwrap(),
+            Command::Find("скайрим".to_string())
+        );
+    }
+
+    #[test]
+    fn free_text_is_not_a_command() {
+        assert!(parse_command("#игры\nЗаголовок: вечер", "notebot").is_none());
+        assert!(matches!(parse_command("/unknown", "notebot"), Some(Err(ParseError::UnknownCommand(_)))));
+    }
+}
@@ -0,0 +1,305 @@
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use chrono::FixedOffset;
+use serde::Deserialize;
+use teloxide::types::ChatId;
+use thiserror::Error;
+
+const CONFIG_VAR: &str = "NOTEBOT_CONFIG";
+const DEFAULT_CONFIG_FILE: &str = "notebot.toml";
+
+const NOTES_FOLDER: &str = "Заметки";
+const ATTACHMENTS_FOLDER: &str = "Вложения";
+const NOTES_DB: &str = "notes.db";
+const DIALOGUES_DB: &str = "dialogues.db";
+
+/// Telegram rejects messages longer than this many characters.
+const TELEGRAM_MESSAGE_LIMIT: usize = 4096;
+/// Telegram renders at most this many buttons in one keyboard row.
+const TELEGRAM_ROW_LIMIT: usize = 8;
+/// More results than this with their snippets may not fit one message.
+const MAX_PAGE_SIZE: usize = 20;
+/// Time zones range from UTC−12:00 to UTC+14:00.
+const MAX_UTC_OFFSET_MINUTES: i32 = 14 * 60;
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("cannot read config file {0}: {1}")]
+    Read(PathBuf, std::io::Error),
+    #[error("invalid config file {0}: {1}")]
+    Parse(PathBuf, toml::de::Error),
+    #[error("invalid value {1:?} in {0}")]
+    Env(&'static str, String),
+    #[error("bot token is not set: put `token` in the config file or set TELOXIDE_TOKEN")]
+    MissingToken,
+    #[error("{0} must be between {1} and {2}, got {3}")]
+    OutOfRange(&'static str, usize, usize, usize),
+    #[error("fuzzy_threshold must be above 0 and at most 1, got {0}")]
+    FuzzyThreshold(f64),
+    #[error("utc_offset_minutes must be between -{max} and {max}, got {0}", max = MAX_UTC_OFFSET_MINUTES)]
+    UtcOffset(i32),
+    #[error("legacy_owner must be a numeric chat id, got {0:?}")]
+    LegacyOwner(String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    Fs,
+    Sqlite,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DialogueBackend {
+    Memory,
+    Sqlite,
+}
+
+/// How search results are ordered; dates go newest first. `Relevance`
+/// keeps the order the store returns: best match first for searches.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NoteOrder {
+    Relevance,
+    Title,
+    Created,
+    Updated,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub token: String,
+    pub data_dir: PathBuf,
+    pub storage: StorageBackend,
+    pub dialogue_storage: DialogueBackend,
+    pub legacy_owner: Option<String>,
+    pub max_tag_title_length: usize,
+    pub max_note_text_length: usize,
+    pub count_column: usize,
+    pub page_size: usize,
+    pub sort_notes: NoteOrder,
+    pub fuzzy_search: bool,
+    pub fuzzy_threshold: f64,
+    pub edit_timeout_secs: u64,
+    pub append_timestamp: bool,
+    pub download_attachments: bool,
+    pub utc_offset_minutes: i32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            token: String::new(),
+            data_dir: PathBuf::from("."),
+            storage: StorageBackend::Fs,
+            dialogue_storage: DialogueBackend::Memory,
+            legacy_owner: None,
+            max_tag_title_length: 64,
+            max_note_text_length: 4000,
+            count_column: 5,
+            page_size: 10,
+            sort_notes: NoteOrder::Relevance,
+            fuzzy_search: true,
+            fuzzy_threshold: 0.75,
+            edit_timeout_secs: 10 * 60,
+            append_timestamp: true,
+            download_attachments: false,
+            utc_offset_minutes: 0,
+        }
+    }
+}
+
+impl Config {
+    /// Reads the TOML file named by `NOTEBOT_CONFIG` (or `notebot.toml` if it
+    /// exists), applies environment overrides and validates the result.
+    pub fn load() -> Result<Self, ConfigError> {
+        let mut config = match std::env::var_os(CONFIG_VAR) {
+            Some(path) => Config::from_file(Path::new(&path))?,
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => Config::from_file(Path::new(DEFAULT_CONFIG_FILE))?,
+            None => Config::default(),
+        };
+
+        config.apply_env()?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let content = std::fs::read_to_string(path).map_err(|e| ConfigError::Read(path.to_path_buf(), e))?;
+
+        toml::from_str(&content).map_err(|e| ConfigError::Parse(path.to_path_buf(), e))
+    }
+
+    fn apply_env(&mut self) -> Result<(), ConfigError> {
+        if let Ok(token) = std::env::var("TELOXIDE_TOKEN") {
+            self.token = token;
+        }
+        if let Some(data_dir) = std::env::var_os("NOTEBOT_DATA_DIR") {
+            self.data_dir = PathBuf::from(data_dir);
+        }
+        if let Ok(owner) = std::env::var("NOTEBOT_LEGACY_OWNER") {
+            self.legacy_owner = Some(owner);
+        }
+        env_override("NOTEBOT_STORAGE", &mut self.storage)?;
+        env_override("NOTEBOT_DIALOGUE_STORAGE", &mut self.dialogue_storage)?;
+        env_override("NOTEBOT_MAX_TAG_TITLE_LENGTH", &mut self.max_tag_title_length)?;
+        env_override("NOTEBOT_MAX_NOTE_TEXT_LENGTH", &mut self.max_note_text_length)?;
+        env_override("NOTEBOT_COUNT_COLUMN", &mut self.count_column)?;
+        env_override("NOTEBOT_PAGE_SIZE", &mut self.page_size)?;
+        env_override("NOTEBOT_SORT_NOTES", &mut self.sort_notes)?;
+        env_override("NOTEBOT_FUZZY_SEARCH", &mut self.fuzzy_search)?;
+        env_override("NOTEBOT_FUZZY_THRESHOLD", &mut self.fuzzy_threshold)?;
+        env_override("NOTEBOT_EDIT_TIMEOUT_SECS", &mut self.edit_timeout_secs)?;
+        env_override("NOTEBOT_APPEND_TIMESTAMP", &mut self.append_timestamp)?;
+        env_override("NOTEBOT_DOWNLOAD_ATTACHMENTS", &mut self.download_attachments)?;
+        env_override("NOTEBOT_UTC_OFFSET_MINUTES", &mut self.utc_offset_minutes)?;
+
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.token.trim().is_empty() {
+            return Err(ConfigError::MissingToken);
+        }
+        check_range("max_tag_title_length", self.max_tag_title_length, 2, TELEGRAM_MESSAGE_LIMIT)?;
+        check_range("max_note_text_length", self.max_note_text_length, 1, TELEGRAM_MESSAGE_LIMIT)?;
+        check_range("count_column", self.count_column, 1, TELEGRAM_ROW_LIMIT)?;
+        check_range("page_size", self.page_size, 1, MAX_PAGE_SIZE)?;
+        if !(self.fuzzy_threshold > 0.0 && self.fuzzy_threshold <= 1.0) {
+            return Err(ConfigError::FuzzyThreshold(self.fuzzy_threshold));
+        }
+        if self.utc_offset_minutes.abs() > MAX_UTC_OFFSET_MINUTES {
+            return Err(ConfigError::UtcOffset(self.utc_offset_minutes));
+        }
+        if let Some(owner) = &self.legacy_owner {
+            owner.trim().parse::<i64>().map_err(|_| ConfigError::LegacyOwner(owner.clone()))?;
+        }
+
+        Ok(())
+    }
+
+    /// The time zone of reminders and of the dates on note cards.
+    pub fn utc_offset(&self) -> FixedOffset {
+        FixedOffset::east_opt(self.utc_offset_minutes * 60).unwrap_or(FixedOffset::east_opt(0).unwrap())
+    }
+
+    /// The chat that receives the notes of the old shared folder; checked
+    /// by `validate`.
+    pub fn legacy_owner(&self) -> Option<ChatId> {
+        self.legacy_owner.as_ref().and_then(|owner| owner.trim().parse().ok()).map(ChatId)
+    }
+
+    pub fn notes_folder(&self) -> PathBuf {
+        self.data_dir.join(NOTES_FOLDER)
+    }
+
+    pub fn attachments_folder(&self) -> PathBuf {
+        self.data_dir.join(ATTACHMENTS_FOLDER)
+    }
+
+    pub fn notes_db(&self) -> PathBuf {
+        self.data_dir.join(NOTES_DB)
+    }
+
+    pub fn dialogues_db(&self) -> PathBuf {
+        self.data_dir.join(DIALOGUES_DB)
+    }
+}
+
+impl FromStr for StorageBackend {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fs" => Ok(StorageBackend::Fs),
+            "sqlite" => Ok(StorageBackend::Sqlite),
+            _ => Err(()),
+        }
+    }
+}
+
+impl FromStr for DialogueBackend {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "memory" => Ok(DialogueBackend::Memory),
+            "sqlite" => Ok(DialogueBackend::Sqlite),
+            _ => Err(()),
+        }
+    }
+}
+
+impl FromStr for NoteOrder {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "relevance" => Ok(NoteOrder::Relevance),
+            "title" => Ok(NoteOrder::Title),
+            "created" => Ok(NoteOrder::Created),
+            "updated" => Ok(NoteOrder::Updated),
+            _ => Err(()),
+        }
+    }
+}
+
+fn env_override<T: FromStr>(name: &'static str, value: &mut T) -> Result<(), ConfigError> {
+    if let Ok(raw) = std::env::var(name) {
+        *value = raw.trim().parse().map_err(|_| ConfigError::Env(name, raw))?;
+    }
+
+    Ok(())
+}
+
+fn check_range(name: &'static str, value: usize, min: usize, max: usize) -> Result<(), ConfigError> {
+    if value < min || value > max {
+        return Err(ConfigError::OutOfRange(name, min, max, value));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn example_config_is_valid() {
+        let config: Config = toml::from_str(include_str!("../notebot.example.toml")).unwrap();
+
+        assert!(config.validate().is_ok());
+        assert_eq!(config.storage, StorageBackend::Fs);
+        assert_eq!(config.sort_notes, NoteOrder::Relevance);
+        assert_eq!(config.notes_folder(), Path::new(".").join("Заметки"));
+    }
+
+    #[test]
+    fn invalid_values_are_rejected() {
+        let config: Config = toml::from_str("token = \"t\"\ncount_column = 20").unwrap();
+        assert!(matches!(config.validate(), Err(ConfigError::OutOfRange("count_column", _, _, 20))));
+
+        let config: Config = toml::from_str("count_column = 3").unwrap();
+        assert!(matches!(config.validate(), Err(ConfigError::MissingToken)));
+
+        let config: Config = toml::from_str("token = \"t\"\nfuzzy_threshold = 1.5").unwrap();
+        assert!(matches!(config.validate(), Err(ConfigError::FuzzyThreshold(_))));
+
+        let config: Config = toml::from_str("token = \"t\"\nutc_offset_minutes = -900").unwrap();
+        assert!(matches!(config.validate(), Err(ConfigError::UtcOffset(-900))));
+
+        let config: Config = toml::from_str("token = \"t\"\nlegacy_owner = \"12345678g\"").unwrap();
+        assert!(matches!(config.validate(), Err(ConfigError::LegacyOwner(_))));
+        let config: Config = toml::from_str("token = \"t\"\nlegacy_owner = \"-100123\"").unwrap();
+        assert_eq!(config.legacy_owner(), Some(ChatId(-100123)));
+
+        assert!(toml::from_str::<Config>("storage = \"redis\"").is_err());
+        assert!(toml::from_str::<Config>("colour = \"red\"").is_err());
+    }
+}
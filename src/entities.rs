@@ -0,0 +1,159 @@
+use std::{cmp::Reverse, ops::Range};
+
+use teloxide::types::{MessageEntity, MessageEntityKind};
+
+use crate::functions::escape_markdown_special_chars;
+
+/// Length of `text` in UTF-16 code units, the unit of entity offsets.
+pub fn utf16_len(text: &str) -> usize {
+    text.encode_utf16().count()
+}
+
+/// The entities of `text[range]`, cut to it and counted from its start.
+/// `entities` belong to the whole `text`, as Telegram sends them.
+pub fn entities_in(entities: &[MessageEntity], text: &str, range: Range<usize>) -> Vec<MessageEntity> {
+    let start = utf16_len(&text[..range.start]);
+    let end = start + utf16_len(&text[range]);
+
+    entities.iter()
+        .filter_map(|entity| {
+            let from = entity.offset.max(start);
+            let to = (entity.offset + entity.length).min(end);
+            (from < to).then(|| MessageEntity { kind: entity.kind.clone(), offset: from - start, length: to - from })
+        })
+        .collect()
+}
+
+/// The entities of `part`, the end of `text` up to its trailing whitespace,
+/// as the body of a note is in the message it came in.
+pub fn entities_of_tail(entities: &[MessageEntity], text: &str, part: &str) -> Vec<MessageEntity> {
+    let end = text.trim_end().len();
+    match end.checked_sub(part.len()) {
+        Some(start) if text[..end].ends_with(part) => entities_in(entities, text, start..end),
+        _ => Vec::new(),
+    }
+}
+
+/// `entities` moved `by` UTF-16 code units to the right, for text put after another one.
+pub fn shift_entities(entities: Vec<MessageEntity>, by: usize) -> Vec<MessageEntity> {
+    entities.into_iter().map(|entity| MessageEntity { offset: entity.offset + by, ..entity }).collect()
+}
+
+/// What MarkdownV2 puts around an entity; `None` for entities Telegram
+/// finds by itself, like links and hashtags.
+fn markup(kind: &MessageEntityKind) -> Option<(String, String)> {
+    let (open, close) = match kind {
+        MessageEntityKind::Bold => ("*".to_string(), "*".to_string()),
+        // `\r` is ignored by Telegram and keeps `___` from being read as underline.
+        MessageEntityKind::Italic => ("_".to_string(), "_\r".to_string()),
+        MessageEntityKind::Underline => ("__".to_string(), "__".to_string()),
+        MessageEntityKind::Strikethrough => ("~".to_string(), "~".to_string()),
+        MessageEntityKind::Spoiler => ("||".to_string(), "||".to_string()),
+        MessageEntityKind::Code => ("`".to_string(), "`".to_string()),
+        MessageEntityKind::Pre { language } => (format!("```{}\n", language.as_deref().unwrap_or_default()), "\n```".to_string()),
+        MessageEntityKind::TextLink { url } => ("[".to_string(), format!("]({})", escape_link(url.as_str()))),
+        MessageEntityKind::TextMention { user } => ("[".to_string(), format!("](tg://user?id={})", user.id)),
+        MessageEntityKind::CustomEmoji { custom_emoji_id } => ("![".to_string(), format!("](tg://emoji?id={})", custom_emoji_id)),
+        _ => return None,
+    };
+
+    Some((open, close))
+}
+
+fn is_code(kind: &MessageEntityKind) -> bool {
+    matches!(kind, MessageEntityKind::Code | MessageEntityKind::Pre { .. })
+}
+
+/// Inside `(...)` of a link only `)` and `\` are special.
+fn escape_link(url: &str) -> String {
+    url.replace('\\', "\\\\").replace(')', "\\)")
+}
+
+/// Inside code only `` ` `` and `\` are special.
+fn escape_code(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('`', "\\`")
+}
+
+/// Renders `text` with its formatting `entities` as MarkdownV2. Entities
+/// that overlap without nesting are cut to fit into the enclosing one, and
+/// nothing is formatted inside code.
+pub fn render_markdown(text: &str, entities: &[MessageEntity]) -> String {
+    let mut pending: Vec<&MessageEntity> = entities.iter()
+        .filter(|entity| entity.length > 0 && markup(&entity.kind).is_some())
+        .collect();
+    // Outer entities first, so that they are opened first.
+    pending.sort_by_key(|entity| (entity.offset, Reverse(entity.length)));
+    let mut pending = pending.into_iter().peekable();
+
+    let mut result = String::with_capacity(text.len());
+    // Open entities with the offset they end at, innermost last.
+    let mut open: Vec<(&MessageEntity, usize)> = Vec::new();
+    let mut position = 0;
+
+    for c in text.chars().map(Some).chain([None]) {
+        while let Some((entity, end)) = open.last() {
+            if *end > position {
+                break;
+            }
+            result.push_str(&markup(&entity.kind).unwrap_or_default().1);
+            open.pop();
+        }
+
+        while let Some(entity) = pending.next_if(|entity| entity.offset <= position) {
+            let in_code = open.iter().any(|(open, _)| is_code(&open.kind));
+            let end = open.last().map_or(entity.offset + entity.length, |(_, parent_end)| (entity.offset + entity.length).min(*parent_end));
+            if in_code || end <= position || c.is_none() {
+                continue;
+            }
+            result.push_str(&markup(&entity.kind).unwrap_or_default().0);
+            open.push((entity, end));
+        }
+
+        let c = match c {
+            Some(c) => c,
+            None => break,
+        };
+        let c = c.to_string();
+        if open.iter().any(|(entity, _)| is_code(&entity.kind)) {
+            result.push_str(&escape_code(&c));
+        } else {
+            result.push_str(&escape_markdown_special_chars(&c));
+        }
+        position += utf16_len(&c);
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entities_follow_the_part_of_the_message_they_belong_to() {
+        let text = "#игры\nСкайрим\nКупить 🐉 *срочно*";
+        let body = text.rfind("Купить").unwrap()..text.len();
+        let entities = vec![MessageEntity::bold(0, 5), MessageEntity::italic(14, 10), MessageEntity::code(24, 3)];
+
+        assert_eq!(entities_in(&entities, text, body), vec![MessageEntity::italic(0, 10), MessageEntity::code(10, 3)]);
+        assert_eq!(entities_of_tail(&entities, &format!("{}\n", text), "Купить 🐉 *срочно*"), vec![MessageEntity::italic(0, 10), MessageEntity::code(10, 3)]);
+        assert_eq!(entities_of_tail(&entities, text, "другой текст"), vec![]);
+        assert_eq!(shift_entities(vec![MessageEntity::bold(1, 2)], 5), vec![MessageEntity::bold(6, 2)]);
+    }
+
+    #[test]
+    fn entities_render_as_markdown() {
+        let entities = vec![
+            MessageEntity::bold(0, 12),
+            MessageEntity::italic(7, 5),
+            MessageEntity::code(13, 5),
+            MessageEntity::text_link("https://example.com/a_(b)".parse().unwrap(), 19, 6),
+        ];
+
+        assert_eq!(
+            render_markdown("Важно: *всё* a_b*c ссылка!", &entities),
+            "*Важно: _\\*всё\\*_\r* `a_b*c` [ссылка](https://example.com/a_(b\\))\\!"
+        );
+        assert_eq!(render_markdown("1. план", &[]), "1\\. план");
+    }
+}
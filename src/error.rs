@@ -0,0 +1,282 @@
+use std::sync::Arc;
+
+use futures::future::BoxFuture;
+use teloxide::{
+    error_handlers::ErrorHandler,
+    payloads::SendMessageSetters,
+    prelude::*,
+    types::ParseMode::MarkdownV2,
+    RequestError,
+};
+use thiserror::Error;
+
+use crate::{
+    functions::escape_markdown_special_chars,
+    query::{QueryError, QueryErrorKind},
+    storage::StorageError,
+};
+
+/// Conflicting titles named in an error message at most.
+const MAX_LISTED_CONFLICTS: usize = 10;
+
+pub type BotResult<T = ()> = Result<T, Error>;
+pub type HandlerResult = Result<(), HandlerError>;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("storage error: {0}")]
+    Storage(#[from] StorageError),
+    #[error("dialogue storage error: {0}")]
+    Dialogue(Box<dyn std::error::Error + Send + Sync>),
+    #[error("validation error: {0}")]
+    Validation(#[from] ValidationError),
+    #[error("telegram error: {0}")]
+    Telegram(#[from] RequestError),
+    #[error("parse error: {0}")]
+    Parse(String),
+}
+
+/// Mistakes in user input; reported back to the chat as a reminder of the syntax.
+#[derive(Debug, Error)]
+pub enum ValidationError {
+    #[error("note does not follow the #tags/title/text syntax")]
+    NoteSyntax,
+    #[error("search does not follow the search syntax")]
+    SearchSyntax,
+    #[error("tag and title are longer than {0} bytes")]
+    TagTitleTooLong(usize),
+    #[error("note text is longer than {0} bytes")]
+    TextTooLong(usize),
+    #[error("tag or title contains forbidden characters")]
+    InvalidChars,
+    #[error("note with this tag and title already exists")]
+    DuplicateTitle,
+    #[error("no note with this id")]
+    UnknownNoteId,
+    #[error("empty search query")]
+    EmptyQuery,
+    #[error("{0}")]
+    Query(#[from] QueryError),
+    #[error("appended text does not fit into {max} bytes, {room} left")]
+    AppendixTooLong { max: usize, room: usize },
+    #[error("title must be a single non-empty line")]
+    TitleSyntax,
+    #[error("tags must be #words separated by spaces")]
+    TagSyntax,
+    #[error("tag command needs two tags")]
+    TagMoveSyntax,
+    #[error("no tag {0}")]
+    UnknownTag(String),
+    #[error("tag {0} already exists")]
+    TagExists(String),
+    #[error("tag cannot move into itself")]
+    TagIntoItself,
+    #[error("notes would share a tag and a title: {0:?}")]
+    TitleConflicts(Vec<String>),
+    #[error("reminder time is not understood")]
+    ReminderSyntax,
+    #[error("reminder time has already passed")]
+    ReminderInPast,
+}
+
+impl From<Box<dyn std::error::Error + Send + Sync>> for Error {
+    fn from(error: Box<dyn std::error::Error + Send + Sync>) -> Self {
+        Error::Dialogue(error)
+    }
+}
+
+impl Error {
+    pub fn in_chat(self, chat_id: ChatId) -> HandlerError {
+        HandlerError { chat_id, error: self }
+    }
+
+    /// The MarkdownV2 message shown to the user instead of the error itself.
+    pub fn user_message(&self) -> String {
+        match self {
+            Error::Validation(error) => error.user_message(),
+            Error::Parse(_) => "*Ошибка\\!*⚠️\nНеизвестная команда или неверные аргументы\\. Список команд: /help".to_string(),
+            Error::Storage(_) | Error::Dialogue(_) => {
+                "*Ошибка\\!*⚠️\nНе удалось прочитать или сохранить заметку\\. Попробуйте ещё раз позже\\.".to_string()
+            },
+            Error::Telegram(_) => {
+                "*Ошибка\\!*⚠️\nTelegram не принял ответ бота\\. Попробуйте ещё раз позже\\.".to_string()
+            },
+        }
+    }
+}
+
+impl ValidationError {
+    pub fn user_message(&self) -> String {
+        match self {
+            ValidationError::NoteSyntax => {
+                "*Ошибка\\!*⚠️\nПозвольте напомнить вам синтаксис добавления заметки:\n\
+                `\\#тег` — тег является темой\\, группирующей все заметки \\(тегов может быть несколько\\)\\,\n\
+                `Заголовок` — каждая заметка должна иметь свой заголовок\\,\n\
+                `Текст самой заметки` — информация\\, которую вы хотите сохранить\\; всё после заголовка\\, хоть в несколько строк\\.".to_string()
+            },
+            ValidationError::SearchSyntax => {
+                "*Ошибка\\!*⚠️\nПозвольте напомнить вам синтаксис поиска заметок:\n\
+                \\#тег — необязательный параметр\\, который поможет вам найти заметки именно с этим тегом\\.\n\
+                Фраза: фраза для поиска — так бот будет искать заметку по её содержимому\\, а не по заголовку\\.\n\
+                Заголовок: фраза для поиска — так бот будет искать заметки строго по их заголовкам\\.".to_string()
+            },
+            ValidationError::TagTitleTooLong(_) => {
+                "*Ошибка\\!*⚠️\n\
+                Тег или заголовок слишком длинные, чтобы бот мог сохранить заметку\\.".to_string()
+            },
+            ValidationError::TextTooLong(_) => {
+                "*Ошибка\\!*⚠️\n\
+                Текст заметки слишкоком длинный, чтобы бот мог его сохранить\\.".to_string()
+            },
+            ValidationError::InvalidChars => {
+                "*Ошибка\\!*⚠️\n\
+                Не допускается использование таких символовов как:\n\
+                \\, /, :, \\*, ?, \\\", \\<, \\>, \\|\\.\n\
+                В тегах `/` разделяет вложенные теги: `\\#работа/rust`\\.".to_string()
+            },
+            ValidationError::DuplicateTitle => {
+                "*Ошибка\\!*⚠️\n\
+                Заметка с таким заголовком уже существует\\.".to_string()
+            },
+            ValidationError::UnknownNoteId => {
+                "*Ошибка\\!*⚠️\nЗаметка с таким ID не найдена\\. \
+                ID указан в карточке заметки\\, например: `/delete 12`".to_string()
+            },
+            ValidationError::EmptyQuery => {
+                "*Ошибка\\!*⚠️\nУкажите, что нужно найти: `/find фраза`".to_string()
+            },
+            ValidationError::Query(error) => query_error_message(error),
+            ValidationError::AppendixTooLong { max, room } => {
+                format!(
+                    "*Ошибка\\!*⚠️\nДописанный текст не поместится в заметку: её текст может занимать не больше {} байт, \
+                    свободно {}\\. Сократите текст или отмените изменение командой /cancel\\.",
+                    max, room
+                )
+            },
+            ValidationError::TitleSyntax => {
+                "*Ошибка\\!*⚠️\nЗаголовок должен быть одной непустой строкой\\. Попробуйте ещё раз или отмените изменение командой /cancel\\.".to_string()
+            },
+            ValidationError::TagSyntax => {
+                "*Ошибка\\!*⚠️\nТеги пишутся через пробел и начинаются с \\#: `\\#работа \\#rust/async`\\. \
+                Попробуйте ещё раз или отмените изменение командой /cancel\\.".to_string()
+            },
+            ValidationError::TagMoveSyntax => {
+                "*Ошибка\\!*⚠️\nУкажите два тега: `/renametag \\#старый \\#новый` или `/mergetag \\#откуда \\#куда`".to_string()
+            },
+            ValidationError::UnknownTag(tag) => {
+                format!("*Ошибка\\!*⚠️\nТега {} нет ни у одной заметки\\. Список тегов: /tags", escape_markdown_special_chars(tag))
+            },
+            ValidationError::TagExists(tag) => {
+                format!("*Ошибка\\!*⚠️\nТег {} уже есть\\. Чтобы объединить теги, используйте /mergetag", escape_markdown_special_chars(tag))
+            },
+            ValidationError::TagIntoItself => {
+                "*Ошибка\\!*⚠️\nНельзя перенести тег в него самого или во вложенный в него тег\\.".to_string()
+            },
+            ValidationError::ReminderSyntax => {
+                "*Ошибка\\!*⚠️\nНе удалось понять\\, когда напомнить\\. Например: `завтра 9:00`\\, `через 2 часа`\\, \
+                `25\\.12 18:30`\\, `в пятницу`\\, `каждый день 8:00`\\, `каждую среду 19:00`\\. \
+                Попробуйте ещё раз или отмените изменение командой /cancel\\.".to_string()
+            },
+            ValidationError::ReminderInPast => {
+                "*Ошибка\\!*⚠️\nЭто время уже прошло\\. Укажите время в будущем или отмените изменение командой /cancel\\.".to_string()
+            },
+            ValidationError::TitleConflicts(conflicts) => {
+                let mut list: Vec<_> = conflicts.iter().take(MAX_LISTED_CONFLICTS).map(|conflict| escape_markdown_special_chars(conflict)).collect();
+                if conflicts.len() > MAX_LISTED_CONFLICTS {
+                    list.push(format!("и ещё {}", conflicts.len() - MAX_LISTED_CONFLICTS));
+                }
+                format!(
+                    "*Ошибка\\!*⚠️\nПосле переноса у тега окажется несколько заметок с одинаковым заголовком:\n{}\n\
+                    Переименуйте или удалите их и повторите команду\\.",
+                    list.join("\n")
+                )
+            },
+        }
+    }
+}
+
+/// Explains what is wrong with a `/find` query and points at the spot with a caret.
+fn query_error_message(error: &QueryError) -> String {
+    let quoted = |text: &str| format!("«{}»", escape_markdown_special_chars(text));
+    let reason = match &error.kind {
+        QueryErrorKind::UnexpectedEnd => "запрос оборвался, ожидалось слово или фраза".to_string(),
+        QueryErrorKind::UnexpectedToken(token) => format!("неожиданное {}", quoted(token)),
+        QueryErrorKind::UnclosedQuote => "не закрыта кавычка".to_string(),
+        QueryErrorKind::UnclosedRegex => "не закрыто регулярное выражение `/…/`".to_string(),
+        QueryErrorKind::UnclosedParen => "не закрыта скобка".to_string(),
+        QueryErrorKind::UnknownField(field) => format!(
+            "неизвестное поле {}, доступны title, body, tag, before и after",
+            quoted(field)
+        ),
+        QueryErrorKind::MissingValue(field) => format!("после {} не указано значение", quoted(&format!("{}:", field))),
+        QueryErrorKind::BadDate(date) => format!("{} не похоже на дату, пишите 2024\\-05\\-31 или 31\\.05\\.2024", quoted(date)),
+        QueryErrorKind::BadRegex(_) => "не удалось разобрать регулярное выражение".to_string(),
+    };
+
+    // Only ` and \ are special inside a pre block.
+    let query = error.query.replace('\\', "\\\\").replace('`', "\\`");
+    let caret = format!("{}^", " ".repeat(error.position.saturating_sub(1)));
+
+    format!(
+        "*Ошибка\\!*⚠️\nНе удалось разобрать запрос: {} \\(символ {}\\)\\.\n```\n{}\n{}\n```",
+        reason, error.position, query, caret
+    )
+}
+
+/// An [`Error`] together with the chat it should be reported to.
+#[derive(Debug, Error)]
+#[error("chat {chat_id}: {error}")]
+pub struct HandlerError {
+    pub chat_id: ChatId,
+    pub error: Error,
+}
+
+/// Logs every handler error and tells the user what went wrong in their chat.
+pub struct ErrorReporter {
+    bot: Bot,
+}
+
+impl ErrorReporter {
+    pub fn new(bot: Bot) -> Arc<Self> {
+        Arc::new(ErrorReporter { bot })
+    }
+}
+
+impl ErrorHandler<HandlerError> for ErrorReporter {
+    fn handle_error(self: Arc<Self>, error: HandlerError) -> BoxFuture<'static, ()> {
+        Box::pin(async move {
+            match &error.error {
+                Error::Validation(_) => log::debug!("{}", error),
+                _ => log::error!("{}", error),
+            }
+
+            let reply = self.bot.send_message(error.chat_id, error.error.user_message())
+                .parse_mode(MarkdownV2)
+                .await;
+            if let Err(reply_error) = reply {
+                log::error!("chat {}: cannot report error: {}", error.chat_id, reply_error);
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errors_map_to_localized_messages() {
+        let error: Error = ValidationError::UnknownNoteId.into();
+        assert!(error.user_message().contains("ID не найдена"));
+
+        let error: Error = StorageError::NotFound(12).into();
+        assert!(error.user_message().starts_with("*Ошибка\\!*"));
+        assert_eq!(error.in_chat(ChatId(42)).chat_id, ChatId(42));
+    }
+
+    #[test]
+    fn query_errors_point_at_the_position() {
+        let error: Error = ValidationError::Query(crate::query::Query::parse("rust AND (go").unwrap_err()).into();
+        assert!(error.user_message().ends_with("```\nrust AND (go\n         ^\n```"));
+    }
+}
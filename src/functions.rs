@@ -1,148 +1,285 @@
 use std::{
-    path::{Path, PathBuf},
-    fs::File,
-    io::{Write, BufReader, BufRead},
+    collections::HashSet,
+    ops::Range,
+    time::{SystemTime, UNIX_EPOCH},
 };
 
+use chrono::{FixedOffset, NaiveDateTime};
 use teloxide::types::{
     InlineKeyboardButton, InlineKeyboardMarkup,
     InlineKeyboardButtonKind,
 };
 
-const COUNT_COLUMN: usize = 5;
+use crate::{
+    attachments::kind_name,
+    callback::{checksum, Callback},
+    checklist::items,
+    config::NoteOrder,
+    entities::render_markdown,
+    pages::Page,
+    reminders::{describe_reminder, format_time},
+    storage::{parent_tag, Analyzer, Note, TAG_SEPARATOR},
+};
 
-pub fn create_folder(folder_name: &str) {
-    let _ = std::fs::create_dir(folder_name);
-}
+/// Words of context shown before the first match of a snippet.
+const SNIPPET_CONTEXT: usize = 3;
+/// Words in a snippet at most.
+const SNIPPET_WORDS: usize = 12;
+/// Tag buttons in a row of the tag browser.
+const TAG_COLUMNS: usize = 2;
+/// Checklist items that get a button; Telegram allows 100 buttons per keyboard.
+const CHECKLIST_BUTTONS: usize = 50;
+/// Characters of a checklist item shown on its button.
+const CHECKLIST_LABEL_LENGTH: usize = 40;
 
-pub fn create_file(file_name: &str, content: &str) {
-    let mut file = File::create(file_name).unwrap();
+pub const SEARCH_PHRASE_PREFIX: &str = "Фраза:";
+pub const SEARCH_TITLE_PREFIX: &str = "Заголовок:";
 
-    file.write_all(content.as_bytes()).unwrap();
+/// How the tag browser orders tags: most used first or alphabetically.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TagOrder {
+    Count,
+    Name,
 }
 
-pub fn search_files_in_directory(search_str: &str, folder_path: &str) -> Vec<PathBuf> {
-    let folder_path = Path::new(folder_path);
+pub fn escape_markdown_special_chars(input: &str) -> String {
+    let mut escaped_str = String::with_capacity(input.len());
 
-    if !folder_path.exists() || !folder_path.is_dir() {
-        return Vec::new();
+    for c in input.chars() {
+        match c {
+            '\\' | '`' | '*' | '_' | '{' | '}' | '[' | ']' | '(' | ')' | '<' | '>' | '#' | '+' | '-' | '.' | '!' | '|' | '~' | '=' => {
+                escaped_str.push('\\');
+                escaped_str.push(c);
+            }
+            _ => escaped_str.push(c),
+        }
     }
 
-    let mut result = Vec::new();
+    escaped_str
+}
+
+/// Puts `notes` in the configured order; `Relevance` keeps the store's order.
+pub fn sort_notes(notes: &mut [Note], order: NoteOrder) {
+    match order {
+        NoteOrder::Relevance => {},
+        NoteOrder::Title => notes.sort_by_cached_key(|note| note.title.to_lowercase()),
+        NoteOrder::Created => notes.sort_by_key(|note| std::cmp::Reverse(note.meta.created_at)),
+        NoteOrder::Updated => notes.sort_by_key(|note| std::cmp::Reverse(note.meta.updated_at)),
+    }
+}
 
-    for entry in std::fs::read_dir(folder_path).unwrap() {
-        let entry = entry.unwrap();
+/// Lists the `notes` of one `page` with a numbered button for each, plus
+/// ◀️/▶️ buttons when there are other pages. With a `query`, every entry
+/// also gets a snippet of the text with the matching words in bold.
+pub fn create_message_and_keyboard(
+    notes: Vec<Note>,
+    page: Page,
+    count_column: usize,
+    query: Option<&str>
+) -> (String, InlineKeyboardMarkup) {
+    let mut message = if page.count > 1 {
+        format!("Список найденных заметок \\(страница {} из {}\\):\n", page.index + 1, page.count)
+    } else {
+        String::from("Список найденных заметок:\n")
+    };
+    let mut count_chunks = 0;
+    let mut inline_keyboard: Vec<Vec<InlineKeyboardButton>> = vec![];
+    let mut chunk = vec![];
 
-        let path = entry.path();
+    let analyzer = Analyzer::default();
+    let terms = query.map(|query| analyzer.terms(query)).unwrap_or_default();
 
-        if let Some(name) = path.file_name() {
-            if let Some(name_str) = name.to_str() {
-                if name_str.to_lowercase().contains(search_str) {
-                    result.push(path);
-                }
+    for (i, note) in (page.index * page.size + 1..).zip(notes) {
+        if query.is_some() {
+            message.push_str(&format!("`{})` {}\n", i, highlight(&note.title, &terms, &analyzer)));
+            let snippet = snippet(&note.text, &terms, &analyzer);
+            if !snippet.is_empty() {
+                message.push_str(&format!("{}\n", snippet));
             }
+        } else {
+            message.push_str(&format!("`{})` {}\n", i, escape_markdown_special_chars(&note.title)));
         }
-    }
 
-    result
-}
+        chunk.push(InlineKeyboardButton::new(i.to_string(), InlineKeyboardButtonKind::CallbackData(Callback::Open(note.id).to_string())));
 
-pub fn search_string_in_filenames(search_str: &str, folder_path: &str) -> Vec<PathBuf> {
-    let mut result = Vec::new();
-    let path = Path::new(folder_path);
-
-    if path.is_dir() {
-        if let Ok(entries) = std::fs::read_dir(path) {
-            for entry in entries.flatten() {
-                let entry_path = entry.path();
-                if entry_path.is_file() {
-                    if let Some(filename) = entry_path.file_name() {
-                        if let Some(filename_str) = filename.to_str() {
-                            if filename_str.to_lowercase().contains(search_str) {
-                                result.push(entry_path.clone());
-                            }
-                        }
-                    }
-                } else if entry_path.is_dir() {
-                    let mut subfolder_files = search_string_in_filenames(search_str, entry_path.to_str().unwrap());
-                    result.append(&mut subfolder_files);
-                }
-            }
+        count_chunks += 1;
+        if count_chunks == count_column {
+            count_chunks = 0;
+            inline_keyboard.push(chunk.clone());
+            chunk.clear();
         }
     }
+    inline_keyboard.push(chunk.clone());
 
-    result
+    let navigation = navigation_row(page, Callback::Page);
+    if !navigation.is_empty() {
+        inline_keyboard.push(navigation);
+    }
+
+    (message, InlineKeyboardMarkup::new(inline_keyboard))
 }
 
-pub fn search_string_inside_files(search_str: &str, folder_path: &str) -> Vec<PathBuf> {
-    let mut result = Vec::new();
-    let path = Path::new(folder_path);
-
-    if path.is_dir() {
-        if let Ok(entries) = std::fs::read_dir(path) {
-            for entry in entries.flatten() {
-                let entry_path = entry.path();
-                if entry_path.is_file() {
-                    if let Ok(file) = std::fs::File::open(&entry_path) {
-                        let reader = BufReader::new(file);
-                        for line in reader.lines() {
-                            if let Ok(line_content) = line {
-                                if line_content.to_lowercase().contains(search_str) {
-                                    result.push(entry_path.clone());
-                                    break;
-                                }
-                            }
-                        }
-                    }
-                } else if entry_path.is_dir() {
-                    let mut subfolder_files = search_string_inside_files(search_str, entry_path.to_str().unwrap());
-                    result.append(&mut subfolder_files);
-                }
-            }
-        }
+/// ◀️/▶️ buttons leading to the neighbouring pages, if there are any.
+fn navigation_row(page: Page, callback: impl Fn(usize) -> Callback) -> Vec<InlineKeyboardButton> {
+    let mut navigation = vec![];
+    if page.has_previous() {
+        navigation.push(InlineKeyboardButton::new("◀️", InlineKeyboardButtonKind::CallbackData(callback(page.index - 1).to_string())));
+    }
+    if page.has_next() {
+        navigation.push(InlineKeyboardButton::new("▶️", InlineKeyboardButtonKind::CallbackData(callback(page.index + 1).to_string())));
     }
 
-    result
+    navigation
 }
 
+/// One page of one level of the tag browser: the tags nested directly in
+/// `parent` (the top-level ones without it) with their note counts, 📁 on
+/// those that have nested tags of their own, then ◀️/▶️, the way back up and
+/// a button switching the order. `tags` come sorted by name, the way the
+/// store returns them, so that callbacks can point at them by index.
+pub fn create_tags_message_and_keyboard(
+    tags: &[(String, usize)],
+    parent: Option<usize>,
+    order: TagOrder,
+    page: usize,
+    page_size: usize
+) -> (String, InlineKeyboardMarkup) {
+    let parent = parent.and_then(|index| Some((index, tags.get(index)?)));
+    let parent_name = parent.map(|(_, (tag, _))| tag.as_str());
+    let parents: HashSet<&str> = tags.iter().filter_map(|(tag, _)| parent_tag(tag)).collect();
 
-pub fn escape_markdown_special_chars(input: &str) -> String {
-    let mut escaped_str = String::with_capacity(input.len());
+    let mut level: Vec<_> = tags.iter().enumerate().filter(|(_, (tag, _))| parent_tag(tag) == parent_name).collect();
+    if order == TagOrder::Count {
+        // The sort is stable, so tags used equally often stay by name.
+        level.sort_by_key(|(_, (_, count))| std::cmp::Reverse(*count));
+    }
+    let page = Page::new(page, level.len(), page_size);
 
-    for c in input.chars() {
-        match c {
-            '\\' | '`' | '*' | '_' | '{' | '}' | '[' | ']' | '(' | ')' | '<' | '>' | '#' | '+' | '-' | '.' | '!' | '|' => {
-                escaped_str.push('\\');
-                escaped_str.push(c);
-            }
-            _ => escaped_str.push(c),
-        }
+    let mut message = match parent_name {
+        Some(parent) => format!(
+            "*Теги в {} \\({}\\):*\nНажмите на тег, чтобы увидеть его заметки\\.",
+            escape_markdown_special_chars(parent), level.len()
+        ),
+        None => format!(
+            "*Ваши теги \\({}\\):*\nНажмите на тег, чтобы увидеть его заметки, или на 📁, чтобы раскрыть вложенные теги\\.",
+            level.len()
+        ),
+    };
+    if page.count > 1 {
+        message.push_str(&format!("\nСтраница {} из {}\\.", page.index + 1, page.count));
     }
 
-    escaped_str
+    let mut inline_keyboard: Vec<Vec<InlineKeyboardButton>> = level[page.range(level.len())]
+        .chunks(TAG_COLUMNS)
+        .map(|row| row.iter().map(|(index, (tag, count))| {
+            let (label, callback) = if parents.contains(tag.as_str()) {
+                (format!("📁 {} ({})", tag, count), Callback::Tags { parent: Some((*index, checksum(tag))), page: 0, order })
+            } else {
+                (format!("{} ({})", tag, count), Callback::tag(*index, tag))
+            };
+            InlineKeyboardButton::new(label, InlineKeyboardButtonKind::CallbackData(callback.to_string()))
+        }).collect())
+        .collect();
+
+    let parent_ref = parent.map(|(index, (tag, _))| (index, checksum(tag)));
+    let navigation = navigation_row(page, |index| Callback::Tags { parent: parent_ref, page: index, order });
+    if !navigation.is_empty() {
+        inline_keyboard.push(navigation);
+    }
+
+    if let Some((index, (tag, count))) = parent {
+        let grandparent = parent_tag(tag).and_then(|grandparent| {
+            Some((tags.binary_search_by(|(tag, _)| tag.as_str().cmp(grandparent)).ok()?, checksum(grandparent)))
+        });
+        inline_keyboard.push(vec![
+            InlineKeyboardButton::new(format!("📄 Все заметки {} ({})", tag, count), InlineKeyboardButtonKind::CallbackData(Callback::tag(index, tag).to_string())),
+        ]);
+        inline_keyboard.push(vec![
+            InlineKeyboardButton::new("⬆️ Назад", InlineKeyboardButtonKind::CallbackData(Callback::Tags { parent: grandparent, page: 0, order }.to_string())),
+        ]);
+    }
+
+    let (label, other) = match order {
+        TagOrder::Count => ("🔤 По имени", TagOrder::Name),
+        TagOrder::Name => ("🔢 По количеству заметок", TagOrder::Count),
+    };
+    inline_keyboard.push(vec![
+        InlineKeyboardButton::new(label, InlineKeyboardButtonKind::CallbackData(Callback::Tags { parent: parent_ref, page: 0, order: other }.to_string())),
+    ]);
+
+    (message, InlineKeyboardMarkup::new(inline_keyboard))
 }
 
-pub fn create_message_and_keyboard(files: Vec<PathBuf>) -> (String, InlineKeyboardMarkup) {
-    let mut message = String::from("Список найденных заметок:\n");
-    let mut i = 1;
-    let mut count_chunks = 0;
-    let mut inline_keyboard: Vec<Vec<InlineKeyboardButton>> = vec![];
-    let mut chunk = vec![];
+/// One button per note, labelled with its title.
+pub fn create_suggestions_keyboard(notes: &[Note]) -> InlineKeyboardMarkup {
+    let inline_keyboard = notes.iter()
+        .map(|note| vec![InlineKeyboardButton::new(note.title.clone(), InlineKeyboardButtonKind::CallbackData(Callback::Open(note.id).to_string()))])
+        .collect::<Vec<_>>();
 
-    for file in files {
-        message.push_str(&format!("`{})` {}\n", i, escape_markdown_special_chars(&file.file_name().unwrap().to_str().unwrap().replace(".txt", ""))));
+    InlineKeyboardMarkup::new(inline_keyboard)
+}
 
-        chunk.push(InlineKeyboardButton::new(i.to_string(), InlineKeyboardButtonKind::CallbackData(file.as_os_str().to_str().unwrap().replace("Заметки\\", "").to_string())));
-        i += 1;
+/// The message that shows a note, with buttons to change it and to check
+/// off its checklist items. Reminder times are shown in `utc_offset`.
+pub fn create_note_card(note: &Note, utc_offset: FixedOffset) -> (String, InlineKeyboardMarkup) {
+    let author = match note.meta.author {
+        Some(author) => format!("[{}](tg://user?id={})", author, author),
+        None => "неизвестен".to_string(),
+    };
+    let message = format!("*Ваша заметка📝:*\n*ID:* {}\n*Теги:* {}\n*Заголовок:* {}\n\
+        *Автор:* {}\n*Создана:* {}\n*Изменена:* {}\n{}{}*Текст:*\n{}",
+        note.id,
+        escape_markdown_special_chars(&note.tag_line()),
+        escape_markdown_special_chars(&note.title),
+        author,
+        format_timestamp(note.meta.created_at, utc_offset),
+        format_timestamp(note.meta.updated_at, utc_offset),
+        note.attachment.as_ref().map_or(String::new(), |attachment| format!("*Вложение:* {}\n", kind_name(attachment.kind))),
+        note.reminder.as_ref().map_or(String::new(), |reminder| {
+            format!("*Напоминание:* {}\n", escape_markdown_special_chars(&describe_reminder(reminder, utc_offset)))
+        }),
+        render_markdown(&note.text, &note.entities)
+    );
 
-        count_chunks += 1;
-        if count_chunks == COUNT_COLUMN {
-            count_chunks = 0;
-            inline_keyboard.push(chunk.clone());
-            chunk.clear();
-        }
+    let checklist = items(&note.text);
+    let mut inline_keyboard: Vec<Vec<InlineKeyboardButton>> = checklist.iter()
+        .enumerate()
+        .take(CHECKLIST_BUTTONS)
+        .map(|(index, item)| {
+            let mut label: String = item.label.chars().take(CHECKLIST_LABEL_LENGTH).collect();
+            if label.len() < item.label.len() {
+                label.push('…');
+            }
+            let label = format!("{} {}", if item.done { "✅" } else { "⬜" }, label);
+            vec![InlineKeyboardButton::new(label, InlineKeyboardButtonKind::CallbackData(Callback::toggle(note.id, index, item.label).to_string()))]
+        })
+        .collect();
+    if checklist.iter().any(|item| item.done) {
+        inline_keyboard.push(vec![
+            InlineKeyboardButton::new("Убрать выполненные", InlineKeyboardButtonKind::CallbackData(Callback::ClearDone(note.id).to_string())),
+        ]);
     }
-    inline_keyboard.push(chunk.clone());
+
+    let mut reminder_row = vec![
+        InlineKeyboardButton::new("Напомнить", InlineKeyboardButtonKind::CallbackData(Callback::Remind(note.id).to_string())),
+    ];
+    if note.reminder.is_some() {
+        reminder_row.push(InlineKeyboardButton::new("Не напоминать", InlineKeyboardButtonKind::CallbackData(Callback::Unremind(note.id).to_string())));
+    }
+    inline_keyboard.extend([
+        vec![
+            InlineKeyboardButton::new("Изменить заголовок", InlineKeyboardButtonKind::CallbackData(Callback::EditTitle(note.id).to_string())),
+            InlineKeyboardButton::new("Изменить теги", InlineKeyboardButtonKind::CallbackData(Callback::EditTags(note.id).to_string())),
+        ],
+        vec![
+            InlineKeyboardButton::new("Изменить текст", InlineKeyboardButtonKind::CallbackData(Callback::Edit(note.id).to_string())),
+            InlineKeyboardButton::new("Дописать", InlineKeyboardButtonKind::CallbackData(Callback::Append(note.id).to_string())),
+        ],
+        reminder_row,
+        vec![
+            InlineKeyboardButton::new("Удалить", InlineKeyboardButtonKind::CallbackData(Callback::Delete(note.id).to_string())),
+        ],
+    ]);
 
     (message, InlineKeyboardMarkup::new(inline_keyboard))
 }
@@ -157,4 +294,294 @@ pub fn contains_invalid_chars(s: &str) -> bool {
     }
 
     false
-}
\ No newline at end of file
+}
+
+/// Like [`contains_invalid_chars`], but lets `/` separate the levels of
+/// nested tags; `parse_tags` rejects empty, `.` and `..` levels.
+pub fn contains_invalid_tag_chars(s: &str) -> bool {
+    s.split(TAG_SEPARATOR).any(contains_invalid_chars)
+}
+
+/// Byte ranges of the words in `text`, split the same way the search index does.
+fn word_spans(text: &str) -> Vec<Range<usize>> {
+    let mut spans = Vec::new();
+    let mut start = None;
+
+    for (i, c) in text.char_indices() {
+        match (c.is_alphanumeric(), start) {
+            (true, None) => start = Some(i),
+            (false, Some(s)) => {
+                spans.push(s..i);
+                start = None;
+            },
+            _ => {},
+        }
+    }
+    if let Some(s) = start {
+        spans.push(s..text.len());
+    }
+
+    spans
+}
+
+/// Escapes `text[range]` for MarkdownV2, puts the words matching `terms` in
+/// bold and squeezes line breaks so that the snippet stays on one line.
+fn highlight_range(text: &str, range: Range<usize>, terms: &[String], analyzer: &Analyzer) -> String {
+    let mut result = String::new();
+    let mut position = range.start;
+
+    for word in word_spans(&text[range.clone()]) {
+        let word = word.start + range.start..word.end + range.start;
+        result.push_str(&escape_markdown_special_chars(&text[position..word.start].replace('\n', " ")));
+        if !terms.is_empty() && analyzer.matches(&text[word.clone()], terms) {
+            result.push_str(&format!("*{}*", escape_markdown_special_chars(&text[word.clone()])));
+        } else {
+            result.push_str(&escape_markdown_special_chars(&text[word.clone()]));
+        }
+        position = word.end;
+    }
+    result.push_str(&escape_markdown_special_chars(&text[position..range.end].replace('\n', " ")));
+
+    result
+}
+
+/// Escapes `text` for MarkdownV2 and puts the words matching `terms` in bold.
+pub fn highlight(text: &str, terms: &[String], analyzer: &Analyzer) -> String {
+    highlight_range(text, 0..text.len(), terms, analyzer)
+}
+
+/// A few words of `text` around the first one matching `terms`, escaped and
+/// highlighted; the beginning of the text if nothing matches.
+pub fn snippet(text: &str, terms: &[String], analyzer: &Analyzer) -> String {
+    let words = word_spans(text);
+    if words.is_empty() {
+        return String::new();
+    }
+
+    let first = words.iter().position(|word| analyzer.matches(&text[word.clone()], terms)).unwrap_or(0);
+    let start = first.saturating_sub(SNIPPET_CONTEXT);
+    let end = (start + SNIPPET_WORDS).min(words.len());
+
+    let mut snippet = String::new();
+    if start > 0 {
+        snippet.push('…');
+    }
+    snippet.push_str(&highlight_range(text, words[start].start..words[end - 1].end, terms, analyzer));
+    if end < words.len() {
+        snippet.push('…');
+    }
+
+    snippet
+}
+
+/// Splits a note message into its tag line, title and text: the first two
+/// lines and everything after them, line breaks included. `None` for fewer
+/// than three lines.
+pub fn split_note(message: &str) -> Option<(&str, &str, &str)> {
+    let mut parts = message.trim().splitn(3, '\n');
+    let tags = parts.next()?.trim();
+    let title = parts.next()?.trim();
+    let text = parts.next()?.trim_start_matches('\n').trim_end();
+
+    Some((tags, title, text))
+}
+
+/// Current Unix time in seconds.
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default()
+}
+
+/// Formats a Unix timestamp for a MarkdownV2 message, in the time zone of
+/// `offset`, as reminders are.
+pub fn format_timestamp(secs: u64, offset: FixedOffset) -> String {
+    match NaiveDateTime::from_timestamp_opt(secs as i64, 0) {
+        Some(_) if secs > 0 => escape_markdown_special_chars(&format_time(secs, offset)),
+        _ => "неизвестно".to_string(),
+    }
+}
+
+/// Formats a Unix timestamp as plain text, in UTC.
+pub fn format_plain_timestamp(secs: u64) -> String {
+    NaiveDateTime::from_timestamp_opt(secs as i64, 0)
+        .map(|time| time.format("%d.%m.%Y %H:%M UTC").to_string())
+        .unwrap_or_default()
+}
+
+/// Whether a search line is an old-style `Заголовок: ...` or `Фраза: ...`
+/// one; elsewhere in a `/find` query `заголовок:` is a field of its own.
+pub fn has_search_prefix(line: &str) -> bool {
+    strip_prefix_ignore_case(line, SEARCH_TITLE_PREFIX).is_some() || strip_prefix_ignore_case(line, SEARCH_PHRASE_PREFIX).is_some()
+}
+
+/// `line` without `prefix` at its start, whatever the case of either.
+pub fn strip_prefix_ignore_case<'a>(line: &'a str, prefix: &str) -> Option<&'a str> {
+    let mut chars = line.chars();
+    for expected in prefix.chars() {
+        if !chars.next()?.to_lowercase().eq(expected.to_lowercase()) {
+            return None;
+        }
+    }
+
+    Some(chars.as_str())
+}
+
+/// `text` with `appendix` added on a new line, after a line with the
+/// `timestamp` if there is one.
+pub fn append_text(text: &str, appendix: &str, timestamp: Option<u64>) -> String {
+    let mut result = text.trim_end().to_string();
+    if !result.is_empty() {
+        result.push_str(if timestamp.is_some() { "\n\n" } else { "\n" });
+    }
+    if let Some(timestamp) = timestamp {
+        result.push_str(&format!("— {} —\n", format_plain_timestamp(timestamp)));
+    }
+    result.push_str(appendix);
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{test_note, NoteMeta};
+
+    fn callbacks(row: &[InlineKeyboardButton]) -> Vec<String> {
+        row.iter().map(|button| match &button.kind {
+            InlineKeyboardButtonKind::CallbackData(data) => data.clone(),
+            _ => String::new(),
+        }).collect()
+    }
+
+    fn note(id: u64, title: &str, created_at: u64) -> Note {
+        let note = test_note("#игры", title, "");
+        Note { id, meta: NoteMeta { created_at, updated_at: created_at, ..note.meta }, ..note }
+    }
+
+    #[test]
+    fn results_are_sorted_by_the_configured_order() {
+        let mut notes = vec![note(1, "Скайрим", 100), note(2, "ведьмак", 300), note(3, "Готика", 200)];
+
+        sort_notes(&mut notes, NoteOrder::Title);
+        let (message, _) = create_message_and_keyboard(notes.clone(), Page::new(0, 3, 10), 5, None);
+        assert!(message.ends_with("`1)` ведьмак\n`2)` Готика\n`3)` Скайрим\n"));
+
+        sort_notes(&mut notes, NoteOrder::Created);
+        let (_, keyboard) = create_message_and_keyboard(notes, Page::new(0, 3, 10), 5, None);
+        assert_eq!(callbacks(&keyboard.inline_keyboard[0]), vec!["o2", "o3", "o1"]);
+    }
+
+    #[test]
+    fn pages_are_numbered_on_and_navigable() {
+        let notes = vec![note(4, "Готика", 100), note(5, "Скайрим", 200)];
+
+        let (message, keyboard) = create_message_and_keyboard(notes, Page::new(1, 7, 3), 5, None);
+        assert!(message.starts_with("Список найденных заметок \\(страница 2 из 3\\):\n`4)` Готика"));
+        assert_eq!(callbacks(&keyboard.inline_keyboard[1]), vec!["p0", "p2"]);
+
+        let (_, keyboard) = create_message_and_keyboard(vec![note(1, "Готика", 100)], Page::new(0, 1, 3), 5, None);
+        assert_eq!(keyboard.inline_keyboard.len(), 1);
+    }
+
+    #[test]
+    fn tags_open_by_their_index_in_name_order() {
+        let tags = vec![("#вечер".to_string(), 1), ("#игры".to_string(), 3), ("#работа".to_string(), 2)];
+
+        let (message, keyboard) = create_tags_message_and_keyboard(&tags, None, TagOrder::Count, 0, 2);
+        assert!(message.starts_with("*Ваши теги \\(3\\):*"));
+        assert_eq!(keyboard.inline_keyboard[0][0].text, "#игры (3)");
+        assert_eq!(callbacks(&keyboard.inline_keyboard[0]), vec![Callback::tag(1, "#игры").to_string(), Callback::tag(2, "#работа").to_string()]);
+        assert_eq!(callbacks(&keyboard.inline_keyboard[0])[0].parse(), Ok(Callback::Tag { index: 1, hash: checksum("#игры") }));
+        assert_ne!(checksum("#игры"), checksum("#работа"));
+        assert_eq!(callbacks(&keyboard.inline_keyboard[1]), vec!["t1"]);
+        assert_eq!(callbacks(&keyboard.inline_keyboard[2]), vec!["n0"]);
+
+        let (_, keyboard) = create_tags_message_and_keyboard(&tags, None, TagOrder::Name, 1, 2);
+        assert_eq!(callbacks(&keyboard.inline_keyboard[0]), vec![Callback::tag(2, "#работа").to_string()]);
+        assert_eq!(callbacks(&keyboard.inline_keyboard[1]), vec!["n0"]);
+        assert_eq!(callbacks(&keyboard.inline_keyboard[2]), vec!["t0"]);
+    }
+
+    #[test]
+    fn nested_tags_drill_down() {
+        let tags = crate::storage::count_tags(["#работа/rust/async", "#работа/go", "#дом"]);
+        let index = |name: &str| tags.iter().position(|(tag, _)| tag == name).unwrap();
+
+        let (_, keyboard) = create_tags_message_and_keyboard(&tags, None, TagOrder::Name, 0, 10);
+        assert_eq!(keyboard.inline_keyboard[0][1].text, "📁 #работа (2)");
+        assert_eq!(callbacks(&keyboard.inline_keyboard[0]), vec![Callback::tag(index("#дом"), "#дом").to_string(), Callback::Tags { parent: Some((index("#работа"), checksum("#работа"))), page: 0, order: TagOrder::Name }.to_string()]);
+
+        let (message, keyboard) = create_tags_message_and_keyboard(&tags, Some(index("#работа/rust")), TagOrder::Name, 0, 10);
+        assert!(message.starts_with("*Теги в \\#работа/rust \\(1\\):*"));
+        assert_eq!(callbacks(&keyboard.inline_keyboard[0]), vec![Callback::tag(index("#работа/rust/async"), "#работа/rust/async").to_string()]);
+        assert_eq!(callbacks(&keyboard.inline_keyboard[1]), vec![Callback::tag(index("#работа/rust"), "#работа/rust").to_string()]);
+        assert_eq!(callbacks(&keyboard.inline_keyboard[2]), vec![Callback::Tags { parent: Some((index("#работа"), checksum("#работа"))), page: 0, order: TagOrder::Name }.to_string()]);
+
+        assert!(!contains_invalid_tag_chars("#работа/rust"));
+        assert!(contains_invalid_tag_chars("#работа\\rust"));
+        assert!(contains_invalid_chars("работа/rust"));
+    }
+
+    #[test]
+    fn snippets_highlight_matching_word_forms() {
+        let analyzer = Analyzer::default();
+        let terms = analyzer.terms("новая игра");
+        let text = "Раз два три четыре пять шесть. Купить новую игру (Скайрим) на распродаже, \
+            если успею до конца недели и если цена не выше тысячи рублей";
+
+        assert_eq!(
+            snippet(text, &terms, &analyzer),
+            "…пять шесть\\. Купить *новую* *игру* \\(Скайрим\\) на распродаже, если успею до конца…"
+        );
+        assert_eq!(snippet("Без совпадений", &terms, &analyzer), "Без совпадений");
+        assert_eq!(highlight("Игры = жизнь", &terms, &analyzer), "*Игры* \\= жизнь");
+    }
+
+    #[test]
+    fn timestamps_are_escaped_for_markdown() {
+        let moscow = FixedOffset::east_opt(3 * 3600).unwrap();
+        assert_eq!(format_timestamp(0, moscow), "неизвестно");
+        assert_eq!(format_timestamp(1_700_000_000, moscow), "15\\.11\\.2023 01:13 \\(UTC\\+03:00\\)");
+    }
+
+    #[test]
+    fn everything_after_the_title_is_the_text() {
+        assert_eq!(
+            split_note("#покупки\nСписок\n- молоко\n\n- хлеб\n"),
+            Some(("#покупки", "Список", "- молоко\n\n- хлеб"))
+        );
+        assert_eq!(split_note(" #игры \n Скайрим \nДраконы"), Some(("#игры", "Скайрим", "Драконы")));
+        assert_eq!(split_note("#игры\nЗаголовок: скайрим\n"), None);
+    }
+
+    #[test]
+    fn appendices_go_on_new_lines() {
+        assert_eq!(append_text("Молоко\n", "Хлеб", None), "Молоко\nХлеб");
+        assert_eq!(append_text("День 1", "День 2", Some(1_700_000_000)), "День 1\n\n— 14.11.2023 22:13 UTC —\nДень 2");
+        assert_eq!(append_text("", "Начало", None), "Начало");
+
+        // An empty appendix measures what the separator and the timestamp take.
+        let frame = append_text("День 1", "", Some(1_700_000_000)).len();
+        assert_eq!(frame + "День 2".len(), append_text("День 1", "День 2", Some(1_700_000_000)).len());
+    }
+
+    #[test]
+    fn checklist_buttons_carry_their_label() {
+        let note = Note { id: 3, ..test_note("#дела", "Покупки", "- [ ] молоко\n- [x] хлеб") };
+        let (_, keyboard) = create_note_card(&note, FixedOffset::east_opt(0).unwrap());
+
+        assert_eq!(callbacks(&keyboard.inline_keyboard[1])[0].parse(), Ok(Callback::Toggle { note: 3, item: 1, hash: checksum("хлеб") }));
+        assert_ne!(checksum("молоко"), checksum("хлеб"));
+    }
+
+    #[test]
+    fn prefix_is_stripped_in_any_case() {
+        assert_eq!(strip_prefix_ignore_case("заГОЛОВОК: вечер", "Заголовок:"), Some(" вечер"));
+        assert_eq!(strip_prefix_ignore_case("Фраза:жить", "Фраза:"), Some("жить"));
+        assert_eq!(strip_prefix_ignore_case("Заголовок:", "Заголовок:"), Some(""));
+        assert_eq!(strip_prefix_ignore_case("Фраз", "Фраза:"), None);
+        assert_eq!(strip_prefix_ignore_case("и Фраза: жить", "Фраза:"), None);
+
+        assert!(has_search_prefix("фраза: жить"));
+        assert!(!has_search_prefix("скайрим NOT заголовок:черновик"));
+    }
+}
@@ -1,56 +1,138 @@
 use teloxide::Bot;
-use tokio::sync::Mutex;
 
 use teloxide::{
+    dispatching::dialogue::{
+        serializer::Json, ErasedStorage, InMemStorage, SqliteStorage, Storage,
+    },
     payloads::SendMessageSetters,
     prelude::*,
     types::{
-        InlineKeyboardButton, InlineKeyboardMarkup,
-        InlineKeyboardButtonKind, Me, ParseMode::MarkdownV2
+        Me, MessageEntity, MessageId, ParseMode::MarkdownV2
     },
+    utils::command::{BotCommands, ParseError},
 };
 
-use std::{
-    error::Error,
-    sync::Arc, path::Path,
-};
+use std::sync::Arc;
 
+mod attachments;
+mod callback;
+mod checklist;
+mod command;
+mod config;
+mod entities;
+mod error;
 mod functions;
+mod pages;
+mod query;
+mod reminders;
+mod scheduler;
+mod state;
+mod storage;
+use attachments::{attachment_of, download, remove_copy, send_attachment};
+use callback::{checksum, Callback};
+use checklist::{clear_done, items, toggle};
+use command::{parse_command, Command};
+use config::{Config, DialogueBackend, StorageBackend};
+use entities::{entities_in, entities_of_tail, shift_entities, utf16_len};
+use pages::{Page, ResultPages, Results};
+use query::Query;
+use reminders::{describe_reminder, format_time, parse_reminder, split_reminder, Reminder};
+use scheduler::{is_snooze_option, run_scheduler};
+use error::{BotResult, Error, ErrorReporter, HandlerResult, ValidationError};
+use state::{NoteDialogue, State};
+use storage::{is_under, move_tag as retag, parse_tags, FsStore, IndexedStore, Note, NoteId, NoteMeta, NoteStore, Search, SqliteStore, StorageError, TagFilter};
 use functions::{
-    create_folder, 
-    create_file, 
-    search_files_in_directory, 
-    search_string_in_filenames,
-    search_string_inside_files,
     escape_markdown_special_chars,
     create_message_and_keyboard,
-    contains_invalid_chars
+    contains_invalid_chars,
+    contains_invalid_tag_chars,
+    create_suggestions_keyboard,
+    create_tags_message_and_keyboard,
+    create_note_card,
+    append_text,
+    split_note,
+    strip_prefix_ignore_case,
+    has_search_prefix,
+    SEARCH_PHRASE_PREFIX,
+    SEARCH_TITLE_PREFIX,
+    now,
+    sort_notes,
+    TagOrder
 };
 
-const MAX_TAG_TITLE_LENGTH: usize = 64;
-const MAX_NOTE_TEXT_LENGTH: usize = 4000;
-const TITLE_OFFSET: usize = SEARCH_TITLE_PREFIX.as_bytes().len() + 1;
-const PHRASE_OFFSET: usize = SEARCH_PHRASE_PREFIX.as_bytes().len() + 1;
 
-const NOTES_FOLDER: &str = "Заметки";
-const SEARCH_PHRASE_PREFIX: &str = "Фраза:";
-const SEARCH_TITLE_PREFIX: &str = "Заголовок:";
-const TOKEN: &str = "token";
+const APPEND_PROMPT: &str = "*Введите текст, который нужно дописать в конец заметки:*\n_Отменить можно командой /cancel\\._";
+const REMINDER_PROMPT: &str = "*Когда напомнить?*⏰\nНапример: `завтра 9:00`\\, `через 2 часа`\\, `25\\.12 18:30`\\, `каждый день 8:00`\n\
+    _Отменить можно командой /cancel\\._";
+
+/// Titles this similar to a query that found nothing are offered instead.
+const SUGGESTION_THRESHOLD: f64 = 0.4;
+const SUGGESTION_COUNT: usize = 3;
+
+const SYNTAX_HELP: &str = "Добавить заметку вы можете с помощью следующего синтаксиса:\n\
+    `\\#тег` — тег является темой\\, группирующей все заметки\\; тегов может быть несколько: `\\#работа \\#rust`\\, \
+    а через `/` теги можно вкладывать друг в друга: `\\#работа/rust/async`\\,\n\
+    `Заголовок` — каждая заметка должна иметь свой заголовок\\,\n\
+    `Текст самой заметки` — информация\\, которую вы хотите сохранить\\; всё после заголовка\\, хоть в несколько строк\\.\n\
+    Давайте я покажу вам пример такой заметки:\n\
+    \\#игры \\#вечер\n\
+    Игра на вечер\n\
+    Хочу поиграть в Скайрим🎮\n\
+    Последней строкой можно попросить напомнить о заметке: `⏰ завтра 9:00`\\, `⏰ через 2 часа`\\, `⏰ каждую пятницу 19:00`\\.\n\
+    Строки вида `\\- [ ] пункт` становятся списком дел: пункты отмечаются кнопками под заметкой✅\\.\n\
+    Также можно подписать фото\\, документ или голосовое сообщение — они сохранятся вместе с заметкой📎\\.\n\
+    После того как вы создали заметки\\, вы можете легко найти их🔎\\.\n\
+    Для поиска заметок используется следующий синтаксис:\n\
+    \\#тег — необязательный параметр\\, который поможет вам найти заметки именно с этим тегом\\. \
+    `\\#работа \\#rust` найдёт заметки с обоими тегами\\, а `\\#работа \\| \\#rust` — хотя бы с одним из них\\. \
+    `\\#работа` найдёт и заметки с вложенными тегами вроде `\\#работа/rust`\\.\n\
+    Фраза: фраза для поиска — так бот будет искать заметку по её содержимому\\, а не по заголовку\\.\n\
+    Заголовок: фраза для поиска — так бот будет искать заметки строго по их заголовкам\\.\n\
+    В однострочном запросе `/find` можно сочетать условия: `AND`\\, `OR`\\, `NOT` и скобки\\, \
+    фразы в кавычках\\, регулярные выражения `/\\.\\*/`\\, а также поля `title:`\\, `body:`\\, `tag:`\\, `before:` и `after:`\\. \
+    Например: `/find \\#работа \\(rust OR go\\) NOT title:черновик after:2024\\-01\\-01`";
+
 
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
-    create_folder(NOTES_FOLDER);
+async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    pretty_env_logger::init();
+    log::info!("Starting NoteBot...");
 
-    let bot = Bot::new(TOKEN);
-    let changing = Arc::new(Mutex::new(String::new()));
+    let config = Arc::new(Config::load()?);
+    std::fs::create_dir_all(&config.data_dir)?;
+
+    let store: Arc<dyn NoteStore> = match config.storage {
+        StorageBackend::Sqlite => Arc::new(IndexedStore::new(SqliteStore::open(config.notes_db())?)?),
+        StorageBackend::Fs => {
+            let store = FsStore::new(config.notes_folder())?;
+            if let Some(owner) = config.legacy_owner() {
+                store.migrate_shared_notes(owner)?;
+            }
+            Arc::new(IndexedStore::new(store)?)
+        },
+    };
+
+    let bot = Bot::new(&config.token);
+    bot.set_my_commands(Command::bot_commands()).await?;
+    let storage: Arc<ErasedStorage<State>> = match config.dialogue_storage {
+        DialogueBackend::Sqlite => SqliteStorage::open(&config.dialogues_db().to_string_lossy(), Json).await?.erase(),
+        DialogueBackend::Memory => InMemStorage::<State>::new().erase(),
+    };
 
     let handler = dptree::entry()
-        .branch(Update::filter_message().endpoint(message_handler))
-        .branch(Update::filter_callback_query().endpoint(callback_handler));
+        .branch(Update::filter_message()
+            .enter_dialogue::<Message, ErasedStorage<State>, State>()
+            .endpoint(message_handler))
+        .branch(Update::filter_callback_query()
+            .enter_dialogue::<CallbackQuery, ErasedStorage<State>, State>()
+            .endpoint(callback_handler));
+
+    tokio::spawn(run_scheduler(bot.clone(), store.clone(), config.clone()));
 
-    Dispatcher::builder(bot, handler)
-        .dependencies(dptree::deps![changing])
+    Dispatcher::builder(bot.clone(), handler)
+        .dependencies(dptree::deps![storage, store, config, Arc::new(ResultPages::default())])
+        .error_handler(ErrorReporter::new(bot))
         .enable_ctrlc_handler()
         .build()
         .dispatch()
@@ -59,228 +141,758 @@ async fn main() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn message_handler(
     bot: Bot,
     msg: Message,
-    _me: Me,
-    changing: Arc<Mutex<String>>
-) -> Result<(), Box<dyn Error + Send + Sync>> {
+    me: Me,
+    dialogue: NoteDialogue,
+    state: State,
+    store: Arc<dyn NoteStore>,
+    config: Arc<Config>,
+    pages: Arc<ResultPages>
+) -> HandlerResult {
+    let chat_id = msg.chat.id;
+
+    handle_message(bot, msg, me, dialogue, state, store, config, pages).await.map_err(|error| error.in_chat(chat_id))
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn handle_message(
+    bot: Bot,
+    msg: Message,
+    me: Me,
+    dialogue: NoteDialogue,
+    state: State,
+    store: Arc<dyn NoteStore>,
+    config: Arc<Config>,
+    pages: Arc<ResultPages>
+) -> BotResult {
     if let Some(text) = msg.text() {
-        match text {
-            "/start" => {
-                bot.send_message(msg.chat.id, &format!(
-                    "Приветствую вас, *{}*\\!👋\n\
-                    Я специальный бот🤖 для хранения ваших заметок📝\\. С помощью меня вы легко сможете сохранить важную информацию и найти её\\.\n\
-                    Добавить заметку вы можете с помощью следующего синтаксиса:\n\
-                    `\\#тег` — тег является темой\\, группирующей все заметки\\,\n\
-                    `Заголовок` — каждая заметка должна иметь свой заголовок\\,\n\
-                    `Текст самой заметки` — информация\\, которую вы хотите сохранить\\.\n\
-                    Давайте я покажу вам пример такой заметки:\n\
-                    \\#игры\n\
-                    Игра на вечер\n\
-                    Хочу поиграть в Скайрим🎮\n\
-                    После того как вы создали заметки\\, вы можете легко найти их🔎\\.\n\
-                    Для поиска заметок используется следующий синтаксис:\n\
-                    \\#тег — необязательный параметр\\, который поможет вам найти заметки именно с этим тегом\\.\n\
-                    Фраза: фраза для поиска — так бот будет искать заметку по её содержимому\\, а не по заголовку\\.\n\
-                    Заголовок: фраза для поиска — так бот будет искать заметки строго по их заголовкам\\.\n\
-                    Это всё\\, что нужно вам знать\\, а теперь добавьте вашу первую заметку\\.",
-                    msg.chat.first_name().unwrap())
-                ).parse_mode(MarkdownV2).await.unwrap();
+        match parse_command(text, me.username()) {
+            Some(Ok(command)) => {
+                return command_handler(bot, msg, command, dialogue, state, store, config, pages).await;
             },
-            _ => {
-                let mut note = changing.lock().await;
+            Some(Err(ParseError::WrongBotName(_))) => return Ok(()),
+            Some(Err(error)) => return Err(Error::Parse(error.to_string())),
+            None => {},
+        }
 
-                if !note.is_empty() {
-                    let _ = std::fs::write(&format!("{}\\{}", NOTES_FOLDER, &*note), text);
-                    bot.send_message(msg.chat.id, "*Заметка изменена\\!*✍️").parse_mode(MarkdownV2).await.unwrap();
-                    note.clear();
-                }
+        if state.is_expired(config.edit_timeout_secs) {
+            dialogue.exit().await?;
+            bot.send_message(msg.chat.id,
+                "*Время на изменение заметки истекло\\.*⌛\nОткройте заметку и нажмите кнопку изменения ещё раз\\."
+            ).parse_mode(MarkdownV2).await?;
+            // The text was meant for the expired edit, not as a new note or a search.
+            return Ok(());
+        } else if state != State::Idle {
+            match edit_note(&bot, msg.chat.id, &state, text, msg.entities().unwrap_or_default(), &store, &config).await {
+                Err(Error::Validation(error)) if !matches!(error, ValidationError::UnknownNoteId) => return Err(error.into()),
+                edited => {
+                    dialogue.exit().await?;
+                    return edited;
+                },
+            }
+        }
 
-                let lines: Vec<_> = text.split('\n').map(|s| s.trim()).collect();
-
-                match lines.len() {
-                    3 => {
-                        if lines[0].chars().nth(0).unwrap() != '#' || lines[0].is_empty() || lines[1].is_empty() || lines[2].is_empty() {
-                            bot.send_message(msg.chat.id,
-                                "*Ошибка\\!*⚠️\nПозвольте напомнить вам синтаксис добавления заметки:\n\
-                                `\\#тег` — тег является темой\\, группирующей все заметки\\,\n\
-                                `Заголовок` — каждая заметка должна иметь свой заголовок\\,\n\
-                                `Текст самой заметки` — информация\\, которую вы хотите сохранить\\."
-                            ).parse_mode(MarkdownV2).await.unwrap();
-                        }
-                        else {
-                            if lines[0].as_bytes().len() + lines[1].as_bytes().len() > MAX_TAG_TITLE_LENGTH {
-                                bot.send_message(msg.chat.id,
-                                    "*Ошибка\\!*⚠️\n\
-                                    Тег или заголовок слишком длинные, чтобы бот мог сохранить заметку\\."
-                                ).parse_mode(MarkdownV2).await.unwrap();
-                            } else if lines[2].len() > MAX_NOTE_TEXT_LENGTH {
-                                bot.send_message(msg.chat.id,
-                                    "*Ошибка\\!*⚠️\n\
-                                    Текст заметки слишкоком длинный, чтобы бот мог его сохранить\\."
-                                ).parse_mode(MarkdownV2).await.unwrap();
-                            } else {
-                                let mut file_error = false;
-                                if !contains_invalid_chars(lines[0]) {
-                                    create_folder(&format!("{}\\{}", NOTES_FOLDER, lines[0]));
-                                    if !contains_invalid_chars(lines[1]) {
-                                        if !Path::new(&format!("{}\\{}\\{}.txt", NOTES_FOLDER, lines[0], lines[1])).exists() {
-                                            create_file(&format!("{}\\{}\\{}.txt", NOTES_FOLDER, lines[0], lines[1]), lines[2]);
-                                            bot.send_message(msg.chat.id, "*Заметка создана\\!*✅").parse_mode(MarkdownV2).await.unwrap();
-                                        } else {
-                                            bot.send_message(msg.chat.id,
-                                                "*Ошибка\\!*⚠️\n\
-                                                Заметка с таким заголовком уже существует\\."
-                                            ).parse_mode(MarkdownV2).await.unwrap();
-                                        }
-                                    }
-                                    else {
-                                        file_error = true;
-                                    }
-                                    
-                                }
-                                else {
-                                    file_error = true;
-                                }
-                                if file_error {
-                                    bot.send_message(msg.chat.id,
-                                        "*Ошибка\\!*⚠️\n\
-                                        Не допускается использование таких символовов как:\n\
-                                        \\, /, :, \\*, ?, \\\", \\<, \\>, \\|\\."
-                                    ).parse_mode(MarkdownV2).await.unwrap();
-                                }
-                            }
-                            
-                        }
-                    },
-                    2 => {
-                        if lines[0].chars().nth(0).unwrap() == '#' && !lines[1].is_empty() &&
-                            (!lines[1].contains(SEARCH_PHRASE_PREFIX) || !lines[1].contains(&SEARCH_PHRASE_PREFIX.to_lowercase()) || 
-                            !lines[1].contains(SEARCH_TITLE_PREFIX) || !lines[1].contains(&SEARCH_TITLE_PREFIX.to_lowercase())) {
-                            if lines[0].chars().nth(0).unwrap() == '#' {
-                                if lines[1].contains(SEARCH_TITLE_PREFIX) || lines[1].contains(&SEARCH_TITLE_PREFIX.to_lowercase()) {
-                                    let search_str = &lines[1][TITLE_OFFSET..];
-                                    let files = search_string_in_filenames(&search_str.trim().to_lowercase(), &format!("Заметки\\{}", lines[0]));
-                                    if !files.is_empty() {
-                                        let (message, inline_keyboard) = create_message_and_keyboard(files);
-                                        bot.send_message(msg.chat.id, message).reply_markup(inline_keyboard).parse_mode(MarkdownV2).await.unwrap();
-                                    } else {
-                                        bot.send_message(msg.chat.id,
-                                            "*Не найдено ни одной заметки\\.*"
-                                        ).parse_mode(MarkdownV2).await.unwrap();
-                                    }
-                                }
-
-                                if lines[1].contains(SEARCH_PHRASE_PREFIX) || lines[1].contains(&SEARCH_PHRASE_PREFIX.to_lowercase()) {
-                                    let search_str = &lines[1][PHRASE_OFFSET..];
-                                    let files = search_string_inside_files(&search_str.trim().to_lowercase(), &format!("Заметки\\{}", lines[0]));
-                                    if !files.is_empty() {
-                                        let (message, inline_keyboard) = create_message_and_keyboard(files);
-                                        bot.send_message(msg.chat.id, message).reply_markup(inline_keyboard).parse_mode(MarkdownV2).await.unwrap();
-                                    } else {
-                                        bot.send_message(msg.chat.id,
-                                            "*Не найдено ни одной заметки\\.*"
-                                        ).parse_mode(MarkdownV2).await.unwrap();
-                                    }
-                                }
-                            }
-                        }
-                        else {
-                            bot.send_message(msg.chat.id,
-                                "*Ошибка\\!*⚠️\nПозвольте напомнить вам синтаксис поиска заметок:\n\
-                                \\#тег — необязательный параметр\\, который поможет вам найти заметки именно с этим тегом\\.\n\
-                                Фраза: фраза для поиска — так бот будет искать заметку по её содержимому\\, а не по заголовку\\.\n\
-                                Заголовок: фраза для поиска — так бот будет искать заметки строго по их заголовкам\\."
-                            ).parse_mode(MarkdownV2).await.unwrap();
+        // Three lines or more are always a note, so a text is never taken for a search.
+        if split_note(text).is_some() {
+            create_note(&bot, &msg, text, &store, &config).await?;
+        } else {
+            let lines: Vec<_> = text.trim().split('\n').map(|s| s.trim()).collect();
+            search_notes(&bot, msg.chat.id, &lines, &store, &config, &pages).await?;
+        }
+    } else if let (Some(caption), Some(_)) = (msg.caption(), attachment_of(&msg)) {
+        // A caption can only be a note: searches and edits are text messages.
+        create_note(&bot, &msg, caption, &store, &config).await?;
+    }
+
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn command_handler(
+    bot: Bot,
+    msg: Message,
+    command: Command,
+    dialogue: NoteDialogue,
+    state: State,
+    store: Arc<dyn NoteStore>,
+    config: Arc<Config>,
+    pages: Arc<ResultPages>
+) -> BotResult {
+    match command {
+        Command::Start => {
+            bot.send_message(msg.chat.id, format!(
+                "Приветствую вас, *{}*\\!👋\n\
+                Я специальный бот🤖 для хранения ваших заметок📝\\. С помощью меня вы легко сможете сохранить важную информацию и найти её\\.\n\
+                {}\n\
+                Это всё\\, что нужно вам знать\\, а теперь добавьте вашу первую заметку\\.",
+                escape_markdown_special_chars(msg.chat.first_name().unwrap_or_default()),
+                SYNTAX_HELP)
+            ).parse_mode(MarkdownV2).await?;
+        },
+        Command::Help => {
+            bot.send_message(msg.chat.id, format!(
+                "{}\n\n*Команды:*\n{}",
+                SYNTAX_HELP,
+                escape_markdown_special_chars(&Command::descriptions().to_string()))
+            ).parse_mode(MarkdownV2).await?;
+        },
+        Command::New(note) => {
+            create_note(&bot, &msg, &note, &store, &config).await?;
+        },
+        Command::Find(query) => {
+            let query = query.trim();
+            let lines: Vec<_> = query.split('\n').map(|s| s.trim()).collect();
+
+            if query.is_empty() {
+                return Err(ValidationError::EmptyQuery.into());
+            } else if lines.len() > 1 || TagFilter::parse(query).is_some()
+                || has_search_prefix(query) {
+                search_notes(&bot, msg.chat.id, &lines, &store, &config, &pages).await?;
+            } else {
+                let parsed = Query::parse(query).map_err(ValidationError::Query)?;
+                let highlight = parsed.highlight_text();
+                match parsed.into_search() {
+                    Search::Any(text) => {
+                        let notes = store.search(msg.chat.id, None, &Search::Any(text.to_lowercase()))?;
+                        if notes.is_empty() {
+                            send_suggestions(&bot, msg.chat.id, &text, &store).await?;
+                        } else {
+                            send_notes(&bot, msg.chat.id, notes, Some(&text), &config, &pages).await?;
                         }
                     },
-                    1 => {
-                        if lines[0].chars().nth(0).unwrap() == '#' {
-                            let files = search_files_in_directory("", &format!("{}\\{}", NOTES_FOLDER, lines[0]));
-                            if !files.is_empty() {
-                                let (message, inline_keyboard) = create_message_and_keyboard(files);
-                                bot.send_message(msg.chat.id, message).reply_markup(inline_keyboard).parse_mode(MarkdownV2).await.unwrap();
-                            } else {
-                                bot.send_message(msg.chat.id,
-                                    "*Не найдено ни одной заметки\\.*"
-                                ).parse_mode(MarkdownV2).await.unwrap();
-                            }
-                        }
-                        if lines[0].contains(SEARCH_TITLE_PREFIX) {
-                            let search_str = &lines[0][TITLE_OFFSET..];
-                            let files = search_string_in_filenames(&search_str.trim().to_lowercase(), NOTES_FOLDER);
-                            if !files.is_empty() {
-                                let (message, inline_keyboard) = create_message_and_keyboard(files);
-                                bot.send_message(msg.chat.id, message).reply_markup(inline_keyboard).parse_mode(MarkdownV2).await.unwrap();
-                            } else {
-                                bot.send_message(msg.chat.id,
-                                    "*Не найдено ни одной заметки\\.*"
-                                ).parse_mode(MarkdownV2).await.unwrap();
-                            }
-                        }
-                        if lines[0].contains(SEARCH_PHRASE_PREFIX) {
-                            let search_str = &lines[0][PHRASE_OFFSET..];
-                            let files = search_string_inside_files(&search_str.trim().to_lowercase(), NOTES_FOLDER);
-                            if !files.is_empty() {
-                                let (message, inline_keyboard) = create_message_and_keyboard(files);
-                                bot.send_message(msg.chat.id, message).reply_markup(inline_keyboard).parse_mode(MarkdownV2).await.unwrap();
-                            } else {
-                                bot.send_message(msg.chat.id,
-                                    "*Не найдено ни одной заметки\\.*"
-                                ).parse_mode(MarkdownV2).await.unwrap();
-                            }
-                        }
+                    search => {
+                        let notes = store.search(msg.chat.id, None, &search)?;
+                        let highlight = Some(highlight.as_str()).filter(|text| !text.is_empty());
+                        send_notes(&bot, msg.chat.id, notes, highlight, &config, &pages).await?;
                     },
-                    _ => {}
                 }
             }
+        },
+        Command::Tags(order) => {
+            let order = match order.trim() {
+                "" => TagOrder::Count,
+                "имя" | "name" => TagOrder::Name,
+                other => return Err(Error::Parse(format!("unknown tag order: {}", other))),
+            };
+
+            let tags = store.tags(msg.chat.id)?;
+            if tags.is_empty() {
+                bot.send_message(msg.chat.id, "*У вас пока нет ни одного тега\\.*").parse_mode(MarkdownV2).await?;
+            } else {
+                let (message, inline_keyboard) = create_tags_message_and_keyboard(&tags, None, order, 0, config.page_size);
+                bot.send_message(msg.chat.id, message).reply_markup(inline_keyboard).parse_mode(MarkdownV2).await?;
+            }
+        },
+        Command::RenameTag(tags) => move_tag(&bot, msg.chat.id, &tags, false, &store, &config).await?,
+        Command::MergeTag(tags) => move_tag(&bot, msg.chat.id, &tags, true, &store, &config).await?,
+        Command::List(tag) => {
+            let tag = tag.trim();
+            let notes = if tag.is_empty() {
+                store.list(msg.chat.id)?
+            } else {
+                let tags = TagFilter::parse(tag).ok_or(ValidationError::SearchSyntax)?;
+                store.list_by_tags(msg.chat.id, &tags)?
+            };
+            send_notes(&bot, msg.chat.id, notes, None, &config, &pages).await?;
+        },
+        Command::Delete(id) => {
+            let note = find_by_id(&store, msg.chat.id, &id)?;
+            store.delete(msg.chat.id, note.id)?;
+            if let Some(attachment) = &note.attachment {
+                remove_copy(attachment).await;
+            }
+            bot.send_message(msg.chat.id, "*Заметка удалена\\!*♻️").parse_mode(MarkdownV2).await?;
+        },
+        Command::Edit(id) => {
+            let note = find_by_id(&store, msg.chat.id, &id)?;
+            dialogue.update(State::awaiting_new_text(note.id)).await?;
+            bot.send_message(msg.chat.id, "*Введите новый текст заметки:*\n_Отменить изменение можно командой /cancel\\._").parse_mode(MarkdownV2).await?;
+        },
+        Command::Append(id) => {
+            let note = find_by_id(&store, msg.chat.id, &id)?;
+            dialogue.update(State::awaiting_appendix(note.id)).await?;
+            bot.send_message(msg.chat.id, APPEND_PROMPT).parse_mode(MarkdownV2).await?;
+        },
+        Command::Remind(args) => {
+            let args = args.trim();
+            let (id, when) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+            let note = find_by_id(&store, msg.chat.id, id)?;
+            if when.trim().is_empty() {
+                dialogue.update(State::awaiting_reminder(note.id)).await?;
+                bot.send_message(msg.chat.id, REMINDER_PROMPT).parse_mode(MarkdownV2).await?;
+            } else {
+                remind(&bot, msg.chat.id, note.id, when, &store, &config).await?;
+            }
+        },
+        Command::Cancel => {
+            if state == State::Idle {
+                bot.send_message(msg.chat.id, "*Нечего отменять\\.*").parse_mode(MarkdownV2).await?;
+            } else {
+                dialogue.exit().await?;
+                bot.send_message(msg.chat.id, "*Изменение отменено\\.*↩️").parse_mode(MarkdownV2).await?;
+            }
+        },
+    }
+
+    Ok(())
+}
+
+fn find_by_id(store: &Arc<dyn NoteStore>, chat_id: ChatId, id: &str) -> BotResult<Note> {
+    let id = id.trim().parse().map_err(|_| ValidationError::UnknownNoteId)?;
+
+    store.get(chat_id, id)?.ok_or_else(|| ValidationError::UnknownNoteId.into())
+}
+
+/// Sends the first page of `notes` and remembers the rest for the ◀️/▶️ buttons.
+async fn send_notes(
+    bot: &Bot,
+    chat_id: ChatId,
+    mut notes: Vec<Note>,
+    query: Option<&str>,
+    config: &Config,
+    pages: &ResultPages
+) -> BotResult {
+    if !notes.is_empty() {
+        sort_notes(&mut notes, config.sort_notes);
+        let ids = notes.iter().map(|note| note.id).collect();
+        let page = Page::new(0, notes.len(), config.page_size);
+        notes.truncate(page.size);
+
+        let (message, inline_keyboard) = create_message_and_keyboard(notes, page, config.count_column, query);
+        let sent = bot.send_message(chat_id, message).reply_markup(inline_keyboard).parse_mode(MarkdownV2).await?;
+        pages.insert(chat_id, Results { message_id: sent.id, notes: ids, query: query.map(str::to_string) });
+    } else {
+        bot.send_message(chat_id,
+            "*Не найдено ни одной заметки\\.*"
+        ).parse_mode(MarkdownV2).await?;
+    }
+
+    Ok(())
+}
+
+/// Saves `message`, the note part of `msg`, keeping the formatting of its
+/// text and the file `msg` carries.
+async fn create_note(
+    bot: &Bot,
+    msg: &Message,
+    message: &str,
+    store: &Arc<dyn NoteStore>,
+    config: &Config
+) -> BotResult {
+    let chat_id = msg.chat.id;
+    let (tag_line, title, body) = split_note(message).ok_or(ValidationError::NoteSyntax)?;
+    let (text, when) = split_reminder(body);
+    if title.is_empty() || text.is_empty() {
+        return Err(ValidationError::NoteSyntax.into());
+    }
+    let tags = parse_tags(tag_line).ok_or(ValidationError::NoteSyntax)?;
+    if text.len() > config.max_note_text_length {
+        return Err(ValidationError::TextTooLong(config.max_note_text_length).into());
+    }
+    check_header(chat_id, None, tag_line, &tags, title, store, config)?;
+
+    let reminder = match when {
+        Some(when) => Some(parse_future_reminder(when, config)?),
+        None => None,
+    };
+
+    let mut attachment = attachment_of(msg);
+    if let Some(attachment) = attachment.as_mut().filter(|_| config.download_attachments) {
+        match download(bot, chat_id, attachment, &config.attachments_folder()).await {
+            Ok(path) => attachment.path = Some(path),
+            Err(error) => log::warn!("cannot save file {}: {}", attachment.file_id, error),
         }
     }
 
+    store.create(chat_id, &Note {
+        id: 0,
+        tags,
+        title: title.to_string(),
+        text: text.to_string(),
+        entities: entities_in(
+            &entities_of_tail(
+                msg.entities().or(msg.caption_entities()).unwrap_or_default(),
+                msg.text().or(msg.caption()).unwrap_or_default(),
+                body
+            ),
+            body,
+            0..text.len()
+        ),
+        attachment,
+        reminder,
+        meta: NoteMeta::new(chat_id, msg.from().map(|user| user.id)),
+    })?;
+    let mut reply = "*Заметка создана\\!*✅".to_string();
+    if let Some(reminder) = &reminder {
+        reply.push_str(&format!("\n*Напомню* {}⏰", escape_markdown_special_chars(&describe_reminder(reminder, config.utc_offset()))));
+    }
+    bot.send_message(chat_id, reply).parse_mode(MarkdownV2).await?;
+
     Ok(())
 }
 
-async fn callback_handler(bot: Bot, q: CallbackQuery, changing: Arc<Mutex<String>>) -> Result<(), Box<dyn Error + Send + Sync>> {
-    if let Some(text) = q.data {
-        if text.contains("#") && text.contains("\\") && text.chars().nth(0).unwrap() != 'd' && text.chars().nth(0).unwrap() != 'w' {
-            let data_of_notes: Vec<_> = text.split("\\").collect();
-
-            let text_from_file = &std::fs::read_to_string(format!("{}\\{}", NOTES_FOLDER, text.clone())).unwrap_or_default();
-
-            if !text_from_file.is_empty() {
-                let message = String::from(format!("*Ваша заметка📝:*\n*Тег:* {}\n*Заголовок:* {}\n*Текст:*\n`{}`",
-                    escape_markdown_special_chars(&data_of_notes[0]),
-                    escape_markdown_special_chars(&data_of_notes[1].replace(".txt", "")),
-                    escape_markdown_special_chars(text_from_file)
-                ));
-
-                let mut inline_keyboard: Vec<Vec<InlineKeyboardButton>> = vec![];
-                let mut chunk = vec![];
-                chunk.push(InlineKeyboardButton::new("Удалить", InlineKeyboardButtonKind::CallbackData(format!("d{}", text.clone()))));
-                chunk.push(InlineKeyboardButton::new("Изменить", InlineKeyboardButtonKind::CallbackData(format!("w{}", text.clone()))));
-                inline_keyboard.push(chunk);
-
-                bot.send_message(q.message.clone().unwrap().chat.id, message)
-                    .reply_markup(InlineKeyboardMarkup::new(inline_keyboard))
-                    .parse_mode(MarkdownV2)
-                    .await
-                    .unwrap();
+async fn search_notes(
+    bot: &Bot,
+    chat_id: ChatId,
+    lines: &[&str],
+    store: &Arc<dyn NoteStore>,
+    config: &Config,
+    pages: &ResultPages
+) -> BotResult {
+    match lines {
+        [tag_line, query] => {
+            if !tag_line.starts_with('#') {
+                return Err(ValidationError::SearchSyntax.into());
             }
+            let tags = TagFilter::parse(tag_line).ok_or(ValidationError::SearchSyntax)?;
+            search_by_prefix(bot, chat_id, Some(&tags), query, store, config, pages).await?;
+        },
+        [line] if line.starts_with('#') => {
+            let tags = TagFilter::parse(line).ok_or(ValidationError::SearchSyntax)?;
+            let notes = store.list_by_tags(chat_id, &tags)?;
+            send_notes(bot, chat_id, notes, None, config, pages).await?;
+        },
+        [line] if has_search_prefix(line) => {
+            search_by_prefix(bot, chat_id, None, line, store, config, pages).await?;
+        },
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Runs a `Заголовок: ...` or `Фраза: ...` search line; any other line,
+/// or a prefix with nothing after it, is a syntax error.
+async fn search_by_prefix(
+    bot: &Bot,
+    chat_id: ChatId,
+    tags: Option<&TagFilter>,
+    line: &str,
+    store: &Arc<dyn NoteStore>,
+    config: &Config,
+    pages: &ResultPages
+) -> BotResult {
+    if let Some(query) = strip_prefix_ignore_case(line, SEARCH_TITLE_PREFIX).map(str::trim).filter(|query| !query.is_empty()) {
+        search_titles(bot, chat_id, tags, query, store, config, pages).await
+    } else if let Some(query) = strip_prefix_ignore_case(line, SEARCH_PHRASE_PREFIX).map(str::trim).filter(|query| !query.is_empty()) {
+        let notes = store.search(chat_id, tags, &Search::Phrase(query.to_lowercase()))?;
+        send_notes(bot, chat_id, notes, Some(query), config, pages).await
+    } else {
+        Err(ValidationError::SearchSyntax.into())
+    }
+}
+
+/// Checks the tags and the title of a new or an edited note: their length,
+/// the characters they use and that no other note under these tags has the
+/// title. `id` is the note being edited, which may keep its own title.
+fn check_header(
+    chat_id: ChatId,
+    id: Option<NoteId>,
+    tag_line: &str,
+    tags: &[String],
+    title: &str,
+    store: &Arc<dyn NoteStore>,
+    config: &Config
+) -> BotResult {
+    if tag_line.len() + title.len() > config.max_tag_title_length {
+        return Err(ValidationError::TagTitleTooLong(config.max_tag_title_length).into());
+    }
+    if contains_invalid_tag_chars(tag_line) || contains_invalid_chars(title) {
+        return Err(ValidationError::InvalidChars.into());
+    }
+    for tag in tags {
+        if store.find(chat_id, tag, title)?.is_some_and(|existing| Some(existing.id) != id) {
+            return Err(ValidationError::DuplicateTitle.into());
         }
-        if text.chars().nth(0).unwrap() == 'd' {
-            let _ = std::fs::remove_file(format!("{}\\{}", NOTES_FOLDER, &text[1..]));
-            bot.edit_message_text(q.message.clone().unwrap().chat.id, q.message.clone().unwrap().id, "*Заметка удалена\\!*♻️")
-                .parse_mode(MarkdownV2)
-                .await
-                .unwrap();
+    }
+
+    Ok(())
+}
+
+/// Applies the change the dialogue waits for. On a validation error the
+/// dialogue keeps waiting, so that the user can try again or /cancel, unless
+/// the note is gone.
+async fn edit_note(
+    bot: &Bot,
+    chat_id: ChatId,
+    state: &State,
+    text: &str,
+    entities: &[MessageEntity],
+    store: &Arc<dyn NoteStore>,
+    config: &Config
+) -> BotResult {
+    match *state {
+        State::Idle => return Ok(()),
+        State::AwaitingNewText { note, .. } => {
+            if text.len() > config.max_note_text_length {
+                return Err(ValidationError::TextTooLong(config.max_note_text_length).into());
+            }
+            store.update(chat_id, note, text, entities).map_err(note_gone)?;
+        },
+        State::AwaitingAppendix { note, .. } => {
+            let note = store.get(chat_id, note)?.ok_or(ValidationError::UnknownNoteId)?;
+            let appendix = text;
+            let timestamp = config.append_timestamp.then(now);
+            let text = append_text(&note.text, appendix, timestamp);
+            if text.len() > config.max_note_text_length {
+                // What is left once the line break and the timestamp line are added.
+                let room = config.max_note_text_length.saturating_sub(append_text(&note.text, "", timestamp).len());
+                return Err(ValidationError::AppendixTooLong { max: config.max_note_text_length, room }.into());
+            }
+            let mut formatting = entities_in(&note.entities, &note.text, 0..note.text.trim_end().len());
+            formatting.extend(shift_entities(entities.to_vec(), utf16_len(&text[..text.len() - appendix.len()])));
+            store.update(chat_id, note.id, &text, &formatting).map_err(note_gone)?;
+        },
+        State::AwaitingReminder { note, .. } => return remind(bot, chat_id, note, text, store, config).await,
+        State::AwaitingNewTitle { note, .. } => {
+            let note = store.get(chat_id, note)?.ok_or(ValidationError::UnknownNoteId)?;
+            let title = text.trim();
+            if title.is_empty() || title.contains('\n') {
+                return Err(ValidationError::TitleSyntax.into());
+            }
+            check_header(chat_id, Some(note.id), &note.tag_line(), &note.tags, title, store, config)?;
+            store.update_header(chat_id, note.id, &note.tags, title).map_err(note_gone)?;
+        },
+        State::AwaitingNewTags { note, .. } => {
+            let note = store.get(chat_id, note)?.ok_or(ValidationError::UnknownNoteId)?;
+            let tag_line = text.trim();
+            let tags = parse_tags(tag_line).ok_or(ValidationError::TagSyntax)?;
+            check_header(chat_id, Some(note.id), tag_line, &tags, &note.title, store, config)?;
+            store.update_header(chat_id, note.id, &tags, &note.title).map_err(note_gone)?;
+        },
+    }
+    bot.send_message(chat_id, "*Заметка изменена\\!*✍️").parse_mode(MarkdownV2).await?;
+
+    Ok(())
+}
+
+/// A note deleted while a change to it was pending is reported to the user
+/// as unknown rather than as a storage failure.
+fn note_gone(error: StorageError) -> Error {
+    match error {
+        StorageError::NotFound(_) => ValidationError::UnknownNoteId.into(),
+        error => error.into(),
+    }
+}
+
+/// Parses when to remind, like `завтра 9:00`, refusing times that have passed.
+fn parse_future_reminder(when: &str, config: &Config) -> BotResult<Reminder> {
+    let now = now();
+    let reminder = parse_reminder(when, now, config.utc_offset()).ok_or(ValidationError::ReminderSyntax)?;
+    if reminder.at <= now {
+        return Err(ValidationError::ReminderInPast.into());
+    }
+
+    Ok(reminder)
+}
+
+/// Sets the reminder of a note to `when` and tells when it goes off.
+async fn remind(
+    bot: &Bot,
+    chat_id: ChatId,
+    note: NoteId,
+    when: &str,
+    store: &Arc<dyn NoteStore>,
+    config: &Config
+) -> BotResult {
+    let reminder = parse_future_reminder(when, config)?;
+    store.set_reminder(chat_id, note, Some(&reminder)).map_err(note_gone)?;
+    bot.send_message(chat_id, format!("*Напомню* {}⏰", escape_markdown_special_chars(&describe_reminder(&reminder, config.utc_offset()))))
+        .parse_mode(MarkdownV2)
+        .await?;
+
+    Ok(())
+}
+
+/// `/renametag #old #new` and `/mergetag #from #to`: both move every note
+/// under the first tag to the second one, but renaming wants a new tag and
+/// merging an existing one.
+async fn move_tag(
+    bot: &Bot,
+    chat_id: ChatId,
+    args: &str,
+    merge: bool,
+    store: &Arc<dyn NoteStore>,
+    config: &Config
+) -> BotResult {
+    let (from, to) = match parse_tags(args).as_deref() {
+        Some([from, to]) => (from.clone(), to.clone()),
+        _ => return Err(ValidationError::TagMoveSyntax.into()),
+    };
+    if contains_invalid_tag_chars(&to) {
+        return Err(ValidationError::InvalidChars.into());
+    }
+    if is_under(&to, &from) {
+        return Err(ValidationError::TagIntoItself.into());
+    }
+
+    let tags = store.tags(chat_id)?;
+    let exists = |tag: &str| tags.iter().any(|(existing, _)| existing == tag);
+    if !exists(&from) {
+        return Err(ValidationError::UnknownTag(from).into());
+    }
+    match (merge, exists(&to)) {
+        (false, true) => return Err(ValidationError::TagExists(to).into()),
+        (true, false) => return Err(ValidationError::UnknownTag(to).into()),
+        _ => {},
+    }
+
+    // The notes are checked the way `check_header` checks a single one.
+    let under = store.list_by_tags(chat_id, &TagFilter::All(vec![from.clone()]))?;
+    let too_long = |note: &Note| note.tag_line().len() + note.title.len() > config.max_tag_title_length;
+    match retag(under, &from, &to) {
+        Ok(moved) if moved.iter().any(too_long) => return Err(ValidationError::TagTitleTooLong(config.max_tag_title_length).into()),
+        Err(StorageError::TitleConflicts(conflicts)) => return Err(ValidationError::TitleConflicts(conflicts).into()),
+        Err(error) => return Err(error.into()),
+        Ok(_) => {},
+    }
+
+    let moved = match store.move_tag(chat_id, &from, &to) {
+        Err(StorageError::TitleConflicts(conflicts)) => return Err(ValidationError::TitleConflicts(conflicts).into()),
+        result => result?,
+    };
+
+    let action = if merge { "перенесены в" } else { "теперь под тегом" };
+    bot.send_message(chat_id, format!(
+        "*Готово\\!*🏷️\nЗаметки с тегом {} {} {}\\. Изменено заметок: {}\\.",
+        escape_markdown_special_chars(&from), action, escape_markdown_special_chars(&to), moved
+    )).parse_mode(MarkdownV2).await?;
+
+    Ok(())
+}
+
+/// Title search that falls back to similar titles when nothing matches
+/// exactly and suggests the closest ones when even those are missing.
+async fn search_titles(
+    bot: &Bot,
+    chat_id: ChatId,
+    tags: Option<&TagFilter>,
+    query: &str,
+    store: &Arc<dyn NoteStore>,
+    config: &Config,
+    pages: &ResultPages
+) -> BotResult {
+    let query = query.trim();
+    let notes = store.search(chat_id, tags, &Search::Title(query.to_lowercase()))?;
+    if !notes.is_empty() {
+        return send_notes(bot, chat_id, notes, Some(query), config, pages).await;
+    }
+
+    if config.fuzzy_search {
+        let similar = store.search(chat_id, tags, &Search::Similar { title: query.to_string(), threshold: config.fuzzy_threshold })?;
+        if !similar.is_empty() {
+            return send_notes(bot, chat_id, similar, None, config, pages).await;
         }
-        if text.chars().nth(0).unwrap() == 'w' {
-            bot.edit_message_text(q.message.clone().unwrap().chat.id, q.message.unwrap().id, "*Введите новый текст заметки:*")
-                .parse_mode(MarkdownV2)
-                .await
-                .unwrap();
-            *changing.lock().await = text[1..].to_string();
+    }
+
+    send_suggestions(bot, chat_id, query, store).await
+}
+
+/// Reports that nothing was found, offering the titles closest to `query` if there are any.
+async fn send_suggestions(bot: &Bot, chat_id: ChatId, query: &str, store: &Arc<dyn NoteStore>) -> BotResult {
+    let mut suggestions = store.search(chat_id, None, &Search::Similar { title: query.to_string(), threshold: SUGGESTION_THRESHOLD })?;
+    suggestions.truncate(SUGGESTION_COUNT);
+
+    if suggestions.is_empty() {
+        bot.send_message(chat_id, "*Не найдено ни одной заметки\\.*").parse_mode(MarkdownV2).await?;
+    } else {
+        bot.send_message(chat_id, "*Не найдено ни одной заметки\\.*\nВозможно, вы имели в виду:")
+            .reply_markup(create_suggestions_keyboard(&suggestions))
+            .parse_mode(MarkdownV2)
+            .await?;
+    }
+
+    Ok(())
+}
+
+async fn callback_handler(
+    bot: Bot,
+    q: CallbackQuery,
+    dialogue: NoteDialogue,
+    store: Arc<dyn NoteStore>,
+    config: Arc<Config>,
+    pages: Arc<ResultPages>
+) -> HandlerResult {
+    let chat_id = dialogue.chat_id();
+    let query_id = q.id.clone();
+
+    let handled = handle_callback(bot.clone(), q, dialogue, store, config, pages).await;
+    // A failed press is answered too, or the button keeps spinning until
+    // Telegram gives up; the error itself is reported in the chat.
+    if handled.is_err() {
+        if let Err(error) = bot.answer_callback_query(query_id).await {
+            log::warn!("cannot answer callback query in chat {}: {}", chat_id, error);
+        }
+    }
+
+    handled.map_err(|error| error.in_chat(chat_id))
+}
+
+async fn handle_callback(
+    bot: Bot,
+    q: CallbackQuery,
+    dialogue: NoteDialogue,
+    store: Arc<dyn NoteStore>,
+    config: Arc<Config>,
+    pages: Arc<ResultPages>
+) -> BotResult {
+    if let Some(data) = q.data {
+        let chat_id = dialogue.chat_id();
+        let message_id = q.message.as_ref().map(|message| message.id);
+
+        match data.parse::<Callback>().map_err(Error::Parse)? {
+            Callback::Open(id) => {
+                if let Some(note) = store.get(chat_id, id)? {
+                    let (message, inline_keyboard) = create_note_card(&note, config.utc_offset());
+
+                    if let Some(attachment) = &note.attachment {
+                        send_attachment(&bot, chat_id, attachment).await?;
+                    }
+                    bot.send_message(chat_id, message)
+                        .reply_markup(inline_keyboard)
+                        .parse_mode(MarkdownV2)
+                        .await?;
+                }
+            },
+            Callback::Delete(id) => {
+                let attachment = store.get(chat_id, id)?.and_then(|note| note.attachment);
+                store.delete(chat_id, id)?;
+                if let Some(attachment) = attachment {
+                    remove_copy(&attachment).await;
+                }
+                replace_or_send(&bot, chat_id, message_id, "*Заметка удалена\\!*♻️").await?;
+            },
+            Callback::Edit(id) => {
+                replace_or_send(&bot, chat_id, message_id, "*Введите новый текст заметки:*\n_Отменить изменение можно командой /cancel\\._").await?;
+                dialogue.update(State::awaiting_new_text(id)).await?;
+            },
+            Callback::Append(id) => {
+                replace_or_send(&bot, chat_id, message_id, APPEND_PROMPT).await?;
+                dialogue.update(State::awaiting_appendix(id)).await?;
+            },
+            Callback::Remind(id) => {
+                replace_or_send(&bot, chat_id, message_id, REMINDER_PROMPT).await?;
+                dialogue.update(State::awaiting_reminder(id)).await?;
+            },
+            Callback::Unremind(id) => {
+                store.set_reminder(chat_id, id, None)?;
+                replace_or_send(&bot, chat_id, message_id, "*Напоминание убрано\\.*🔕").await?;
+            },
+            Callback::Snooze { note, minutes } => {
+                // Callback data comes from the client, so only the offered delays are taken.
+                if !is_snooze_option(minutes) {
+                    return Err(Error::Parse(format!("unknown snooze delay: {}", minutes)));
+                }
+                match store.get(chat_id, note)? {
+                    Some(note) => {
+                        let until = now() + minutes as u64 * 60;
+                        store.set_reminder(chat_id, note.id, Some(&Reminder::snooze(note.reminder, until)))?;
+                        let message = format!("*Напомню* {}⏰", escape_markdown_special_chars(&format_time(until, config.utc_offset())));
+                        replace_or_send(&bot, chat_id, message_id, &message).await?;
+                    },
+                    None => {
+                        bot.answer_callback_query(q.id).text("Эта заметка уже удалена.").await?;
+                        return Ok(());
+                    },
+                }
+            },
+            Callback::Toggle { note, item, hash } => {
+                let note = store.get(chat_id, note)?.ok_or(ValidationError::UnknownNoteId)?;
+                let unchanged = items(&note.text).get(item).is_some_and(|found| checksum(found.label) == hash);
+                match toggle(&note.text, item).filter(|_| unchanged) {
+                    Some(text) => {
+                        store.update(chat_id, note.id, &text, &note.entities)?;
+                        replace_card(&bot, chat_id, message_id, note.id, &store, &config).await?;
+                    },
+                    None => {
+                        bot.answer_callback_query(q.id).text("Список изменился, нажмите на пункт ещё раз.").await?;
+                        replace_card(&bot, chat_id, message_id, note.id, &store, &config).await?;
+                        return Ok(());
+                    },
+                }
+            },
+            Callback::ClearDone(id) => {
+                let note = store.get(chat_id, id)?.ok_or(ValidationError::UnknownNoteId)?;
+                if !items(&note.text).iter().any(|item| item.done) {
+                    bot.answer_callback_query(q.id).text("Выполненных пунктов нет.").await?;
+                    return Ok(());
+                }
+                let (text, entities) = clear_done(&note.text, &note.entities);
+                store.update(chat_id, note.id, &text, &entities)?;
+                replace_card(&bot, chat_id, message_id, note.id, &store, &config).await?;
+            },
+            Callback::EditTitle(id) => {
+                replace_or_send(&bot, chat_id, message_id, "*Введите новый заголовок заметки:*\n_Отменить изменение можно командой /cancel\\._").await?;
+                dialogue.update(State::awaiting_new_title(id)).await?;
+            },
+            Callback::EditTags(id) => {
+                replace_or_send(&bot, chat_id, message_id,
+                    "*Введите новые теги заметки через пробел:* `\\#работа \\#rust`\n_Отменить изменение можно командой /cancel\\._"
+                ).await?;
+                dialogue.update(State::awaiting_new_tags(id)).await?;
+            },
+            Callback::Page(index) => {
+                let results = message_id.and_then(|message_id| pages.get(chat_id, message_id));
+                match (message_id, results) {
+                    (Some(message_id), Some(results)) => {
+                        let page = Page::new(index, results.notes.len(), config.page_size);
+                        let mut notes = Vec::with_capacity(page.size);
+                        for &id in &results.notes[page.range(results.notes.len())] {
+                            notes.extend(store.get(chat_id, id)?);
+                        }
+
+                        let (message, inline_keyboard) = create_message_and_keyboard(notes, page, config.count_column, results.query.as_deref());
+                        bot.edit_message_text(chat_id, message_id, message)
+                            .reply_markup(inline_keyboard)
+                            .parse_mode(MarkdownV2)
+                            .await?;
+                    },
+                    _ => {
+                        bot.answer_callback_query(q.id).text("Эти результаты устарели, повторите поиск.").await?;
+                        return Ok(());
+                    },
+                }
+            },
+            Callback::Tags { parent, page, order } => {
+                let tags = store.tags(chat_id)?;
+                let parent = match parent {
+                    Some((index, hash)) if tags.get(index).is_some_and(|(tag, _)| checksum(tag) == hash) => Some(index),
+                    Some(_) => {
+                        bot.answer_callback_query(q.id).text("Список тегов изменился, откройте /tags ещё раз.").await?;
+                        return Ok(());
+                    },
+                    None => None,
+                };
+                if tags.is_empty() {
+                    replace_or_send(&bot, chat_id, message_id, "*У вас пока нет ни одного тега\\.*").await?;
+                } else {
+                    let (message, inline_keyboard) = create_tags_message_and_keyboard(&tags, parent, order, page, config.page_size);
+                    match message_id {
+                        Some(message_id) => {
+                            bot.edit_message_text(chat_id, message_id, message).reply_markup(inline_keyboard).parse_mode(MarkdownV2).await?;
+                        },
+                        None => {
+                            bot.send_message(chat_id, message).reply_markup(inline_keyboard).parse_mode(MarkdownV2).await?;
+                        },
+                    }
+                }
+            },
+            Callback::Tag { index, hash } => {
+                let tags = store.tags(chat_id)?;
+                match tags.into_iter().nth(index) {
+                    Some((tag, _)) if checksum(&tag) == hash => {
+                        let notes = store.list_by_tags(chat_id, &TagFilter::All(vec![tag]))?;
+                        send_notes(&bot, chat_id, notes, None, &config, &pages).await?;
+                    },
+                    _ => {
+                        bot.answer_callback_query(q.id).text("Список тегов изменился, откройте /tags ещё раз.").await?;
+                        return Ok(());
+                    },
+                }
+            },
         }
 
         bot.answer_callback_query(q.id).await?;
@@ -288,3 +900,43 @@ async fn callback_handler(bot: Bot, q: CallbackQuery, changing: Arc<Mutex<String
 
     Ok(())
 }
+
+/// Shows the card of a note that just changed in place of the message the
+/// pressed button belongs to, as [`replace_or_send`] does for text.
+async fn replace_card(
+    bot: &Bot,
+    chat_id: ChatId,
+    message_id: Option<MessageId>,
+    id: NoteId,
+    store: &Arc<dyn NoteStore>,
+    config: &Config
+) -> BotResult {
+    let note = store.get(chat_id, id)?.ok_or(ValidationError::UnknownNoteId)?;
+    let (message, inline_keyboard) = create_note_card(&note, config.utc_offset());
+
+    match message_id {
+        Some(message_id) => {
+            bot.edit_message_text(chat_id, message_id, message).reply_markup(inline_keyboard).parse_mode(MarkdownV2).await?;
+        },
+        None => {
+            bot.send_message(chat_id, message).reply_markup(inline_keyboard).parse_mode(MarkdownV2).await?;
+        },
+    }
+
+    Ok(())
+}
+
+/// Edits the message the pressed button belongs to, or sends a new one if
+/// Telegram no longer provides it (e.g. the message is too old).
+async fn replace_or_send(bot: &Bot, chat_id: ChatId, message_id: Option<MessageId>, text: &str) -> BotResult {
+    match message_id {
+        Some(message_id) => {
+            bot.edit_message_text(chat_id, message_id, text).parse_mode(MarkdownV2).await?;
+        },
+        None => {
+            bot.send_message(chat_id, text).parse_mode(MarkdownV2).await?;
+        },
+    }
+
+    Ok(())
+}
@@ -0,0 +1,94 @@
+use std::{collections::HashMap, ops::Range, sync::Mutex};
+
+use teloxide::types::{ChatId, MessageId};
+
+use crate::storage::NoteId;
+
+/// Which part of a result list a message shows; `index` counts from 0.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Page {
+    pub index: usize,
+    pub count: usize,
+    pub size: usize,
+}
+
+impl Page {
+    /// Page `index` of `total` results, clamped to the last page.
+    pub fn new(index: usize, total: usize, size: usize) -> Self {
+        let count = total.div_ceil(size).max(1);
+
+        Page { index: index.min(count - 1), count, size }
+    }
+
+    /// Positions of the results on this page.
+    pub fn range(&self, total: usize) -> Range<usize> {
+        (self.index * self.size).min(total)..((self.index + 1) * self.size).min(total)
+    }
+
+    pub fn has_previous(&self) -> bool {
+        self.index > 0
+    }
+
+    pub fn has_next(&self) -> bool {
+        self.index + 1 < self.count
+    }
+}
+
+/// The last result list sent to a chat, already sorted.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Results {
+    pub message_id: MessageId,
+    pub notes: Vec<NoteId>,
+    pub query: Option<String>,
+}
+
+/// Remembers the last search of every chat so that the ◀️/▶️ buttons can
+/// flip its pages. Only the latest message of a chat can be paged; results
+/// are forgotten when the bot restarts.
+#[derive(Default)]
+pub struct ResultPages {
+    chats: Mutex<HashMap<ChatId, Results>>,
+}
+
+impl ResultPages {
+    pub fn insert(&self, chat_id: ChatId, results: Results) {
+        self.chats.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).insert(chat_id, results);
+    }
+
+    /// The results shown in `message_id`, unless a newer search replaced them.
+    pub fn get(&self, chat_id: ChatId, message_id: MessageId) -> Option<Results> {
+        self.chats.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+            .get(&chat_id)
+            .filter(|results| results.message_id == message_id)
+            .cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pages_cover_every_result_once() {
+        let page = Page::new(0, 25, 10);
+        assert_eq!((page.count, page.range(25)), (3, 0..10));
+        assert!(!page.has_previous() && page.has_next());
+
+        let page = Page::new(7, 25, 10);
+        assert_eq!((page.index, page.range(25)), (2, 20..25));
+        assert!(page.has_previous() && !page.has_next());
+
+        assert_eq!(Page::new(0, 0, 10).range(0), 0..0);
+    }
+
+    #[test]
+    fn only_the_latest_results_can_be_paged() {
+        let pages = ResultPages::default();
+        pages.insert(ChatId(42), Results { message_id: MessageId(1), notes: vec![1, 2], query: None });
+        pages.insert(ChatId(42), Results { message_id: MessageId(2), notes: vec![3], query: None });
+
+        assert_eq!(pages.get(ChatId(42), MessageId(1)), None);
+        assert_eq!(pages.get(ChatId(42), MessageId(2)).unwrap().notes, vec![3]);
+        assert_eq!(pages.get(ChatId(7), MessageId(2)), None);
+    }
+}
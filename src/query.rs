@@ -0,0 +1,430 @@
+//! The `/find` query language:
+//!
+//! ```text
+//! query   := or
+//! or      := and (("OR" | "ИЛИ" | "|") and)*
+//! and     := unary (("AND" | "И")? unary)*
+//! unary   := ("NOT" | "НЕ" | "-") unary | primary
+//! primary := "(" or ")" | field ":" value | "#tag" | "/regex/" | "\"phrase\"" | word
+//! field   := "tag" | "title" | "body" | "before" | "after"
+//! ```
+//!
+//! Words and phrases match word forms the way the search index does; dates
+//! are `2024-01-31` or `31.01.2024` and compare with the creation time.
+
+use chrono::NaiveDate;
+use regex::{Regex, RegexBuilder};
+use thiserror::Error;
+
+use crate::storage::{Analyzer, Note, Search};
+
+/// Regexes from users are compiled with this size limit, in bytes.
+const REGEX_SIZE_LIMIT: usize = 1 << 20;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Field {
+    Any,
+    Title,
+    Body,
+}
+
+#[derive(Clone, Debug)]
+pub enum Query {
+    /// Words that must follow each other; `raw` is what the user typed.
+    Text { field: Field, raw: String, terms: Vec<String> },
+    Regex { field: Field, regex: Regex },
+    Tag(String),
+    /// Created before the start of the day, Unix seconds.
+    Before(u64),
+    /// Created on that day or later, Unix seconds.
+    After(u64),
+    Not(Box<Query>),
+    And(Vec<Query>),
+    Or(Vec<Query>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum QueryErrorKind {
+    UnexpectedEnd,
+    UnexpectedToken(String),
+    UnclosedQuote,
+    UnclosedRegex,
+    UnclosedParen,
+    UnknownField(String),
+    MissingValue(String),
+    BadDate(String),
+    BadRegex(String),
+}
+
+/// A query that cannot be parsed; `position` counts characters from 1.
+#[derive(Clone, Debug, Error, PartialEq)]
+#[error("invalid query {query:?} at {position}: {kind:?}")]
+pub struct QueryError {
+    pub query: String,
+    pub position: usize,
+    pub kind: QueryErrorKind,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Word(String),
+    Phrase(String),
+    Regex(String),
+    Field(String),
+    Tag(String),
+    LParen,
+    RParen,
+    And,
+    Or,
+    Not,
+}
+
+/// A token and the character it starts at, counting from 0.
+type Spanned = (Token, usize);
+
+fn tokenize(input: &str) -> Result<Vec<Spanned>, (usize, QueryErrorKind)> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let start = i;
+        match chars[i] {
+            c if c.is_whitespace() => i += 1,
+            '(' => {
+                tokens.push((Token::LParen, start));
+                i += 1;
+            },
+            ')' => {
+                tokens.push((Token::RParen, start));
+                i += 1;
+            },
+            '|' => {
+                tokens.push((Token::Or, start));
+                i += 1;
+            },
+            '-' => {
+                tokens.push((Token::Not, start));
+                i += 1;
+            },
+            '"' => {
+                let end = chars[i + 1..].iter().position(|c| *c == '"').ok_or((start, QueryErrorKind::UnclosedQuote))?;
+                tokens.push((Token::Phrase(chars[i + 1..i + 1 + end].iter().collect()), start));
+                i += end + 2;
+            },
+            '/' => {
+                let mut pattern = String::new();
+                i += 1;
+                loop {
+                    match chars.get(i) {
+                        None => return Err((start, QueryErrorKind::UnclosedRegex)),
+                        Some('/') => break,
+                        Some('\\') if chars.get(i + 1) == Some(&'/') => {
+                            pattern.push('/');
+                            i += 2;
+                        },
+                        Some(c) => {
+                            pattern.push(*c);
+                            i += 1;
+                        },
+                    }
+                }
+                tokens.push((Token::Regex(pattern), start));
+                i += 1;
+            },
+            _ => {
+                let name_length = chars[i..].iter().take_while(|c| c.is_alphabetic()).count();
+                if name_length > 0 && chars.get(i + name_length) == Some(&':') {
+                    let field: String = chars[i..i + name_length].iter().collect();
+                    tokens.push((Token::Field(field.to_lowercase()), start));
+                    i += name_length + 1;
+                    continue;
+                }
+
+                let length = chars[i..].iter()
+                    .position(|c| c.is_whitespace() || matches!(c, '(' | ')' | '"' | '|'))
+                    .unwrap_or(chars.len() - i);
+                tokens.push((word_token(chars[i..i + length].iter().collect()), start));
+                i += length;
+            },
+        }
+    }
+
+    Ok(tokens)
+}
+
+fn word_token(word: String) -> Token {
+    match word.as_str() {
+        "AND" | "И" => Token::And,
+        "OR" | "ИЛИ" => Token::Or,
+        "NOT" | "НЕ" => Token::Not,
+        _ if word.starts_with('#') => Token::Tag(word),
+        _ => Token::Word(word),
+    }
+}
+
+struct Parser<'a> {
+    tokens: Vec<Spanned>,
+    next: usize,
+    /// Where the query ends, for errors about a missing token.
+    end: usize,
+    analyzer: &'a Analyzer,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next).map(|(token, _)| token)
+    }
+
+    fn position(&self) -> usize {
+        self.tokens.get(self.next).map_or(self.end, |(_, position)| *position)
+    }
+
+    fn bump(&mut self) -> Option<Spanned> {
+        let token = self.tokens.get(self.next).cloned();
+        self.next += 1;
+        token
+    }
+
+    fn or(&mut self) -> Result<Query, (usize, QueryErrorKind)> {
+        let mut alternatives = vec![self.and()?];
+        while self.peek() == Some(&Token::Or) {
+            self.bump();
+            alternatives.push(self.and()?);
+        }
+
+        Ok(if alternatives.len() == 1 { alternatives.remove(0) } else { Query::Or(alternatives) })
+    }
+
+    fn and(&mut self) -> Result<Query, (usize, QueryErrorKind)> {
+        let mut parts = vec![self.unary()?];
+        loop {
+            match self.peek() {
+                Some(Token::And) => {
+                    self.bump();
+                },
+                Some(Token::Or) | Some(Token::RParen) | None => break,
+                _ => {},
+            }
+            parts.push(self.unary()?);
+        }
+
+        Ok(if parts.len() == 1 { parts.remove(0) } else { Query::And(parts) })
+    }
+
+    fn unary(&mut self) -> Result<Query, (usize, QueryErrorKind)> {
+        if self.peek() == Some(&Token::Not) {
+            self.bump();
+            return Ok(Query::Not(Box::new(self.unary()?)));
+        }
+
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Query, (usize, QueryErrorKind)> {
+        let position = self.position();
+        let (token, _) = self.bump().ok_or((position, QueryErrorKind::UnexpectedEnd))?;
+
+        match token {
+            Token::LParen => {
+                let query = self.or()?;
+                match self.bump() {
+                    Some((Token::RParen, _)) => Ok(query),
+                    _ => Err((position, QueryErrorKind::UnclosedParen)),
+                }
+            },
+            Token::Word(word) | Token::Phrase(word) => Ok(self.text(Field::Any, word)),
+            Token::Regex(pattern) => regex(Field::Any, &pattern).map_err(|kind| (position, kind)),
+            Token::Tag(tag) => Ok(Query::Tag(tag)),
+            Token::Field(field) => self.field(field, position),
+            Token::RParen | Token::And | Token::Or | Token::Not => {
+                Err((position, QueryErrorKind::UnexpectedToken(token_text(&token))))
+            },
+        }
+    }
+
+    fn field(&mut self, field: String, position: usize) -> Result<Query, (usize, QueryErrorKind)> {
+        let value_position = self.position();
+        let value = match self.bump() {
+            Some((Token::Word(value), _)) | Some((Token::Phrase(value), _)) | Some((Token::Tag(value), _)) => Ok(value),
+            Some((Token::Regex(pattern), _)) => Err(pattern),
+            _ => return Err((position, QueryErrorKind::MissingValue(field))),
+        };
+
+        let text_field = match field.as_str() {
+            "title" | "заголовок" => Some(Field::Title),
+            "body" | "текст" => Some(Field::Body),
+            _ => None,
+        };
+
+        match (field.as_str(), text_field, value) {
+            (_, Some(text_field), Ok(value)) => Ok(self.text(text_field, value)),
+            (_, Some(text_field), Err(pattern)) => regex(text_field, &pattern).map_err(|kind| (value_position, kind)),
+            ("tag" | "тег", _, Ok(tag)) => Ok(Query::Tag(if tag.starts_with('#') { tag } else { format!("#{}", tag) })),
+            ("before" | "до", _, Ok(date)) => parse_date(&date).map(Query::Before).ok_or((value_position, QueryErrorKind::BadDate(date))),
+            ("after" | "после", _, Ok(date)) => parse_date(&date).map(Query::After).ok_or((value_position, QueryErrorKind::BadDate(date))),
+            ("tag" | "тег" | "before" | "до" | "after" | "после", _, Err(_)) => {
+                Err((value_position, QueryErrorKind::UnexpectedToken("/".to_string())))
+            },
+            _ => Err((position, QueryErrorKind::UnknownField(field))),
+        }
+    }
+
+    fn text(&self, field: Field, raw: String) -> Query {
+        let terms = self.analyzer.terms(&raw);
+
+        Query::Text { field, raw, terms }
+    }
+}
+
+fn token_text(token: &Token) -> String {
+    match token {
+        Token::Word(word) | Token::Field(word) | Token::Tag(word) => word.clone(),
+        Token::Phrase(phrase) => format!("\"{}\"", phrase),
+        Token::Regex(pattern) => format!("/{}/", pattern),
+        Token::LParen => "(".to_string(),
+        Token::RParen => ")".to_string(),
+        Token::And => "AND".to_string(),
+        Token::Or => "OR".to_string(),
+        Token::Not => "NOT".to_string(),
+    }
+}
+
+fn regex(field: Field, pattern: &str) -> Result<Query, QueryErrorKind> {
+    let regex = RegexBuilder::new(pattern)
+        .case_insensitive(true)
+        .size_limit(REGEX_SIZE_LIMIT)
+        .build()
+        .map_err(|e| QueryErrorKind::BadRegex(e.to_string()))?;
+
+    Ok(Query::Regex { field, regex })
+}
+
+/// Start of the day in UTC as Unix seconds.
+fn parse_date(date: &str) -> Option<u64> {
+    let date = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .or_else(|_| NaiveDate::parse_from_str(date, "%d.%m.%Y"))
+        .ok()?;
+
+    u64::try_from(date.and_hms_opt(0, 0, 0)?.timestamp()).ok()
+}
+
+impl Query {
+    pub fn parse(input: &str) -> Result<Self, QueryError> {
+        let analyzer = Analyzer::default();
+        let error = |(position, kind): (usize, QueryErrorKind)| QueryError { query: input.to_string(), position: position + 1, kind };
+
+        let tokens = tokenize(input).map_err(error)?;
+        let mut parser = Parser { tokens, next: 0, end: input.chars().count(), analyzer: &analyzer };
+        let query = parser.or().map_err(error)?;
+
+        match parser.bump() {
+            None => Ok(query),
+            Some((token, position)) => Err(error((position, QueryErrorKind::UnexpectedToken(token_text(&token))))),
+        }
+    }
+
+    /// The search to run: a lone word or phrase goes through the ranked
+    /// full-text search, which only finds words next to each other; anything
+    /// else, several words included, is checked note by note.
+    pub fn into_search(self) -> Search {
+        match self {
+            Query::Text { field: Field::Any, raw, .. } => Search::Any(raw),
+            query => Search::Query(query),
+        }
+    }
+
+    /// Words and phrases the query looks for, to highlight them in results.
+    pub fn highlight_text(&self) -> String {
+        match self {
+            Query::Text { raw, .. } => raw.clone(),
+            Query::And(parts) | Query::Or(parts) => {
+                parts.iter().map(Query::highlight_text).filter(|text| !text.is_empty()).collect::<Vec<_>>().join(" ")
+            },
+            _ => String::new(),
+        }
+    }
+
+    pub fn matches(&self, note: &Note) -> bool {
+        self.eval(note, &Analyzer::default())
+    }
+
+    fn eval(&self, note: &Note, analyzer: &Analyzer) -> bool {
+        let in_field = |field: &Field, check: &dyn Fn(&str) -> bool| match field {
+            Field::Any => check(&note.title) || check(&note.text),
+            Field::Title => check(&note.title),
+            Field::Body => check(&note.text),
+        };
+
+        match self {
+            Query::Text { field, terms, .. } => in_field(field, &|text| analyzer.contains(text, terms)),
+            Query::Regex { field, regex } => in_field(field, &|text| regex.is_match(text)),
+            Query::Tag(tag) => note.has_tag_under(tag),
+            Query::Before(time) => note.meta.created_at < *time,
+            Query::After(time) => note.meta.created_at >= *time,
+            Query::Not(query) => !query.eval(note, analyzer),
+            Query::And(parts) => parts.iter().all(|part| part.eval(note, analyzer)),
+            Query::Or(parts) => parts.iter().any(|part| part.eval(note, analyzer)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{test_note, NoteMeta};
+
+    fn note(tags: &str, title: &str, text: &str, created_at: u64) -> Note {
+        let note = test_note(tags, title, text);
+        Note { id: 1, meta: NoteMeta { created_at, updated_at: created_at, ..note.meta }, ..note }
+    }
+
+    fn matches(query: &str, note: &Note) -> bool {
+        Query::parse(query).unwrap().matches(note)
+    }
+
+    #[test]
+    fn operators_fields_and_dates() {
+        // 2024-03-10
+        let note = note("#игры #вечер", "Игра на вечер", "Хочу поиграть в Скайрим или в Ведьмака", 1_710_028_800);
+
+        assert!(matches("скайрим ведьмак", &note));
+        assert!(matches("скайрим AND NOT готика", &note));
+        assert!(!matches("скайрим -ведьмак", &note));
+        assert!(matches("готика OR (ведьмак tag:игры)", &note));
+        assert!(matches("готика | #вечер", &note));
+        assert!(matches("title:\"игры на вечер\"", &note));
+        assert!(!matches("body:вечер", &note));
+        assert!(matches("скайрим NOT заголовок:черновик", &note));
+        assert!(!matches("\"!!!\"", &note));
+        assert!(matches("body:/скай\\w+ или/", &note));
+        assert!(matches("after:2024-03-10 before:11.03.2024", &note));
+        assert!(!matches("after:2024-03-11", &note));
+        assert!(!matches("tag:работа ИЛИ НЕ вечер", &note));
+    }
+
+    #[test]
+    fn lone_words_and_phrases_use_full_text_search() {
+        let search = |query: &str| Query::parse(query).unwrap().into_search();
+
+        assert!(matches!(search("\"новая игра\""), Search::Any(text) if text == "новая игра"));
+        assert!(matches!(search("новая игра"), Search::Query(_)));
+        assert!(matches!(search("новая -игра"), Search::Query(_)));
+        assert_eq!(Query::parse("title:скайрим OR ведьмак").unwrap().highlight_text(), "скайрим ведьмак");
+    }
+
+    #[test]
+    fn errors_point_at_the_problem() {
+        let error = |query: &str| Query::parse(query).unwrap_err();
+
+        assert_eq!(error("скайрим AND").position, 12);
+        assert_eq!(error("скайрим AND").kind, QueryErrorKind::UnexpectedEnd);
+        assert_eq!(error("(игра OR кино").kind, QueryErrorKind::UnclosedParen);
+        assert_eq!(error("игра \"на вечер").position, 6);
+        assert_eq!(error("after:вчера").kind, QueryErrorKind::BadDate("вчера".to_string()));
+        assert_eq!(error("after:вчера").position, 7);
+        assert_eq!(error("color:red").kind, QueryErrorKind::UnknownField("color".to_string()));
+        assert!(matches!(error("/(/").kind, QueryErrorKind::BadRegex(_)));
+        assert_eq!(error("игра )").kind, QueryErrorKind::UnexpectedToken(")".to_string()));
+    }
+}
@@ -0,0 +1,307 @@
+use chrono::{Datelike, Duration, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Weekday};
+use serde::{Deserialize, Serialize};
+
+/// What a reminder without a time of day is set to.
+const DEFAULT_HOUR: u32 = 9;
+
+const MINUTE: u64 = 60;
+const HOUR: u64 = 60 * MINUTE;
+const DAY: u64 = 24 * HOUR;
+
+/// When the bot sends a note back to its chat. Times are Unix seconds.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Reminder {
+    pub at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repeat: Option<Repeat>,
+    /// Set by a snooze button; a repeating reminder keeps its schedule meanwhile.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snoozed: Option<u64>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Repeat {
+    Daily,
+    Weekly,
+}
+
+impl Repeat {
+    fn period(self) -> u64 {
+        match self {
+            Repeat::Daily => DAY,
+            Repeat::Weekly => 7 * DAY,
+        }
+    }
+}
+
+impl Reminder {
+    pub fn once(at: u64) -> Self {
+        Reminder { at, repeat: None, snoozed: None }
+    }
+
+    /// When the reminder goes off next.
+    pub fn due(&self) -> u64 {
+        self.snoozed.map_or(self.at, |snoozed| snoozed.min(self.at))
+    }
+
+    /// The reminder after it went off at `now`, or `None` if it is done.
+    /// A repeating reminder skips the repetitions it missed, so that a bot
+    /// that was down sends it once and not for every day it was away.
+    pub fn after(&self, now: u64) -> Option<Reminder> {
+        let snoozed = self.snoozed.filter(|&snoozed| snoozed > now);
+        if self.at > now {
+            return Some(Reminder { snoozed, ..*self });
+        }
+
+        match self.repeat {
+            Some(repeat) => {
+                let missed = (now - self.at) / repeat.period() + 1;
+                Some(Reminder { at: self.at + missed * repeat.period(), snoozed, ..*self })
+            },
+            None => snoozed.map(Reminder::once),
+        }
+    }
+
+    /// The reminder put off until `until`; a done one comes back as a one-off.
+    pub fn snooze(reminder: Option<Reminder>, until: u64) -> Reminder {
+        match reminder {
+            Some(reminder) => Reminder { snoozed: Some(until), ..reminder },
+            None => Reminder::once(until),
+        }
+    }
+}
+
+/// The day a reminder is set for, before the time of day is known.
+enum Day {
+    /// Today plus this many days.
+    FromToday(i64),
+    /// `25.12` without a year: the next such date.
+    NextDate(u32, u32),
+    Date(NaiveDate),
+    Weekday(Weekday),
+}
+
+/// Parses when to remind: `⏰ завтра 9:00`, `через 2 часа`, `25.12 18:30`,
+/// `в пятницу`, `каждый день 8:00`, `каждую среду 19:00`. Days and times
+/// are in `offset`; `now` is Unix seconds. The result may be in the past,
+/// as `сегодня 8:00` is in the evening.
+pub fn parse_reminder(input: &str, now: u64, offset: FixedOffset) -> Option<Reminder> {
+    let input = input.trim().trim_start_matches('⏰').to_lowercase();
+    let mut words = input.split_whitespace().filter(|word| !matches!(*word, "в" | "во")).peekable();
+
+    let mut repeat = None;
+    let mut day = None;
+    let mut time = None;
+    let mut at = None;
+
+    while let Some(word) = words.next() {
+        match word {
+            "каждый" | "каждую" | "каждое" => {
+                let next = words.next()?;
+                match next {
+                    "день" => repeat = Some(Repeat::Daily),
+                    "неделю" => repeat = Some(Repeat::Weekly),
+                    _ => {
+                        day = Some(Day::Weekday(weekday(next)?));
+                        repeat = Some(Repeat::Weekly);
+                    },
+                }
+            },
+            "ежедневно" => repeat = Some(Repeat::Daily),
+            "еженедельно" => repeat = Some(Repeat::Weekly),
+            "через" => {
+                let count = match words.peek().and_then(|word| word.parse::<u64>().ok()) {
+                    Some(count) => {
+                        words.next();
+                        count
+                    },
+                    None => 1,
+                };
+                at = Some(now + count.checked_mul(unit(words.next()?)?)?);
+            },
+            "сегодня" => day = Some(Day::FromToday(0)),
+            "завтра" => day = Some(Day::FromToday(1)),
+            "послезавтра" => day = Some(Day::FromToday(2)),
+            _ => {
+                if let Some(parsed) = parse_time(word) {
+                    time = Some(parsed);
+                } else if let Some(parsed) = parse_day(word) {
+                    day = Some(parsed);
+                } else {
+                    day = Some(Day::Weekday(weekday(word)?));
+                }
+            },
+        }
+    }
+
+    if let Some(at) = at {
+        return (day.is_none() && time.is_none()).then_some(Reminder { at, repeat, snoozed: None });
+    }
+    // A bare `каждый день` goes off at the default hour.
+    if day.is_none() && time.is_none() && repeat.is_none() {
+        return None;
+    }
+
+    let local_now = offset.from_utc_datetime(&NaiveDateTime::from_timestamp_opt(now as i64, 0)?).naive_local();
+    let today = local_now.date();
+    let time = time.unwrap_or(NaiveTime::from_hms_opt(DEFAULT_HOUR, 0, 0)?);
+    let date = match day {
+        Some(Day::FromToday(days)) => today + Duration::days(days),
+        Some(Day::Date(date)) => date,
+        Some(Day::NextDate(day, month)) => (0..=4)
+            .filter_map(|years| NaiveDate::from_ymd_opt(today.year() + years, month, day))
+            .find(|date| date.and_time(time) > local_now)?,
+        Some(Day::Weekday(weekday)) => {
+            let days = (weekday.num_days_from_monday() + 7 - today.weekday().num_days_from_monday()) % 7;
+            let date = today + Duration::days(days.into());
+            if date.and_time(time) > local_now { date } else { date + Duration::days(7) }
+        },
+        None if today.and_time(time) > local_now => today,
+        None => today + Duration::days(1),
+    };
+
+    let at = offset.from_local_datetime(&date.and_time(time)).single()?.timestamp();
+
+    Some(Reminder { at: u64::try_from(at).ok()?, repeat, snoozed: None })
+}
+
+fn parse_time(word: &str) -> Option<NaiveTime> {
+    let (hours, minutes) = word.split_once(':')?;
+
+    NaiveTime::from_hms_opt(hours.parse().ok()?, minutes.parse().ok()?, 0)
+}
+
+/// `25.12` or `25.12.2026`.
+fn parse_day(word: &str) -> Option<Day> {
+    let mut parts = word.split('.');
+    let day = parts.next()?.parse().ok()?;
+    let month = parts.next()?.parse().ok()?;
+
+    match (parts.next(), parts.next()) {
+        (None, _) => {
+            // Checked against a leap year, so that 29.02 waits for the next one.
+            NaiveDate::from_ymd_opt(2000, month, day)?;
+            Some(Day::NextDate(day, month))
+        },
+        (Some(year), None) => NaiveDate::from_ymd_opt(year.parse().ok()?, month, day).map(Day::Date),
+        _ => None,
+    }
+}
+
+fn weekday(word: &str) -> Option<Weekday> {
+    match word {
+        "понедельник" => Some(Weekday::Mon),
+        "вторник" => Some(Weekday::Tue),
+        "среду" | "среда" => Some(Weekday::Wed),
+        "четверг" => Some(Weekday::Thu),
+        "пятницу" | "пятница" => Some(Weekday::Fri),
+        "субботу" | "суббота" => Some(Weekday::Sat),
+        "воскресенье" => Some(Weekday::Sun),
+        _ => None,
+    }
+}
+
+/// Seconds in a unit of `через N ...`.
+fn unit(word: &str) -> Option<u64> {
+    match word {
+        "минуту" | "минуты" | "минут" | "мин" => Some(MINUTE),
+        "час" | "часа" | "часов" | "ч" => Some(HOUR),
+        "день" | "дня" | "дней" => Some(DAY),
+        "неделю" | "недели" | "недель" => Some(7 * DAY),
+        _ => None,
+    }
+}
+
+/// Splits a `⏰ ...` last line off the text of a note.
+pub fn split_reminder(text: &str) -> (&str, Option<&str>) {
+    let (rest, last_line) = text.rsplit_once('\n').unwrap_or(("", text));
+
+    if last_line.trim_start().starts_with('⏰') {
+        (rest.trim_end(), Some(last_line))
+    } else {
+        (text, None)
+    }
+}
+
+/// `19.10.2026 09:00 (UTC+03:00)`, as plain text.
+pub fn format_time(secs: u64, offset: FixedOffset) -> String {
+    NaiveDateTime::from_timestamp_opt(secs as i64, 0)
+        .map(|time| offset.from_utc_datetime(&time).format("%d.%m.%Y %H:%M (UTC%:z)").to_string())
+        .unwrap_or_default()
+}
+
+/// When the reminder goes off and how often, as plain text.
+pub fn describe_reminder(reminder: &Reminder, offset: FixedOffset) -> String {
+    let when = format_time(reminder.due(), offset);
+
+    match reminder.repeat {
+        Some(Repeat::Daily) => format!("{}, каждый день", when),
+        Some(Repeat::Weekly) => format!("{}, каждую неделю", when),
+        None => when,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sunday, 18.10.2026 12:00 in UTC+3.
+    const NOW: u64 = 1_792_314_000;
+
+    fn moscow() -> FixedOffset {
+        FixedOffset::east_opt(3 * 3600).unwrap()
+    }
+
+    fn at(input: &str) -> Option<String> {
+        parse_reminder(input, NOW, moscow()).map(|reminder| describe_reminder(&reminder, moscow()))
+    }
+
+    #[test]
+    fn reminders_are_parsed_in_local_time() {
+        assert_eq!(format_time(NOW, moscow()), "18.10.2026 12:00 (UTC+03:00)");
+        assert_eq!(at("⏰ завтра 9:00").as_deref(), Some("19.10.2026 09:00 (UTC+03:00)"));
+        assert_eq!(at("через 2 часа").as_deref(), Some("18.10.2026 14:00 (UTC+03:00)"));
+        assert_eq!(at("через час").as_deref(), Some("18.10.2026 13:00 (UTC+03:00)"));
+        assert_eq!(at("25.12 18:30").as_deref(), Some("25.12.2026 18:30 (UTC+03:00)"));
+        assert_eq!(at("01.01.2027").as_deref(), Some("01.01.2027 09:00 (UTC+03:00)"));
+        assert_eq!(at("10.10").as_deref(), Some("10.10.2027 09:00 (UTC+03:00)"));
+        assert_eq!(at("29.02").as_deref(), Some("29.02.2028 09:00 (UTC+03:00)"));
+        assert_eq!(at("8:00").as_deref(), Some("19.10.2026 08:00 (UTC+03:00)"));
+        assert_eq!(at("в пятницу").as_deref(), Some("23.10.2026 09:00 (UTC+03:00)"));
+        assert_eq!(at("в воскресенье 11:00").as_deref(), Some("25.10.2026 11:00 (UTC+03:00)"));
+        assert_eq!(at("каждый день 8:00").as_deref(), Some("19.10.2026 08:00 (UTC+03:00), каждый день"));
+        assert_eq!(at("каждую среду 19:00").as_deref(), Some("21.10.2026 19:00 (UTC+03:00), каждую неделю"));
+        assert_eq!(at("каждый день").as_deref(), Some("19.10.2026 09:00 (UTC+03:00), каждый день"));
+        assert_eq!(at("каждую неделю").as_deref(), Some("19.10.2026 09:00 (UTC+03:00), каждую неделю"));
+
+        assert_eq!(parse_reminder("сегодня 8:00", NOW, moscow()).map(|reminder| reminder.at < NOW), Some(true));
+        assert_eq!(at("когда-нибудь"), None);
+        assert_eq!(at("завтра 25:00"), None);
+        assert_eq!(at("через 2 года"), None);
+        assert_eq!(at(""), None);
+    }
+
+    #[test]
+    fn reminder_line_ends_the_note() {
+        assert_eq!(split_reminder("Купить молоко\n\n⏰ завтра 9:00"), ("Купить молоко", Some("⏰ завтра 9:00")));
+        assert_eq!(split_reminder("⏰ завтра"), ("", Some("⏰ завтра")));
+        assert_eq!(split_reminder("⏰ в начале\nи текст"), ("⏰ в начале\nи текст", None));
+    }
+
+    #[test]
+    fn reminders_repeat_and_snooze() {
+        let daily = Reminder { at: NOW, repeat: Some(Repeat::Daily), snoozed: None };
+        assert_eq!(daily.after(NOW).map(|reminder| reminder.at), Some(NOW + DAY));
+        assert_eq!(daily.after(NOW + 3 * DAY + 1).map(|reminder| reminder.at), Some(NOW + 4 * DAY));
+        assert_eq!(Reminder::once(NOW).after(NOW), None);
+
+        let snoozed = Reminder::snooze(daily.after(NOW), NOW + 10 * MINUTE);
+        assert_eq!(snoozed.due(), NOW + 10 * MINUTE);
+        let after_snooze = snoozed.after(NOW + 10 * MINUTE).unwrap();
+        assert_eq!((after_snooze.due(), after_snooze.snoozed), (NOW + DAY, None));
+
+        assert_eq!(Reminder::snooze(None, NOW + HOUR), Reminder::once(NOW + HOUR));
+        assert_eq!(Reminder::snooze(Some(Reminder::once(NOW + DAY)), NOW + HOUR).after(NOW + HOUR), Some(Reminder::once(NOW + DAY)));
+    }
+}
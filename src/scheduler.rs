@@ -0,0 +1,88 @@
+use std::{sync::Arc, time::Duration};
+
+use teloxide::{
+    payloads::SendMessageSetters,
+    prelude::*,
+    types::{InlineKeyboardButton, InlineKeyboardButtonKind, ParseMode::MarkdownV2},
+};
+
+use crate::{
+    attachments::send_attachment,
+    callback::Callback,
+    config::Config,
+    error::BotResult,
+    functions::{create_note_card, now},
+    storage::{Note, NoteStore},
+};
+
+/// How often the scheduler looks for due reminders; reminders are set to
+/// the minute, so they go off at most this late.
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Snooze buttons under a reminder: label and minutes.
+const SNOOZE_OPTIONS: [(&str, usize); 3] = [("⏰ 10 минут", 10), ("⏰ 1 час", 60), ("⏰ Завтра", 24 * 60)];
+
+/// Whether a snooze button offers to put a reminder off by `minutes`.
+pub fn is_snooze_option(minutes: usize) -> bool {
+    SNOOZE_OPTIONS.iter().any(|&(_, option)| option == minutes)
+}
+
+/// Sends the card of every note whose reminder is due, for as long as the
+/// bot runs. Reminders live in the store, so those that came due while the
+/// bot was down go off once it is back.
+pub async fn run_scheduler(bot: Bot, store: Arc<dyn NoteStore>, config: Arc<Config>) {
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        interval.tick().await;
+        if let Err(error) = send_due_reminders(&bot, &store, &config).await {
+            log::error!("cannot send reminders: {}", error);
+        }
+    }
+}
+
+async fn send_due_reminders(bot: &Bot, store: &Arc<dyn NoteStore>, config: &Config) -> BotResult {
+    let now = now();
+
+    for note in store.due_reminders(now)? {
+        let reminder = match note.reminder {
+            Some(reminder) if reminder.due() <= now => reminder,
+            _ => continue,
+        };
+
+        // The reminder moves on even if the chat can't be reached, so that
+        // a blocked bot does not retry it forever.
+        if let Err(error) = send_reminder(bot, &note, config).await {
+            log::warn!("cannot remind of note {} in chat {}: {}", note.id, note.meta.chat_id, error);
+        }
+        match store.advance_reminder(note.meta.chat_id, note.id, &reminder, reminder.after(now).as_ref()) {
+            Ok(true) => {},
+            Ok(false) => log::info!("reminder of note {} in chat {} changed while it went off", note.id, note.meta.chat_id),
+            Err(error) => log::error!("cannot move on the reminder of note {} in chat {}: {}", note.id, note.meta.chat_id, error),
+        }
+    }
+
+    Ok(())
+}
+
+async fn send_reminder(bot: &Bot, note: &Note, config: &Config) -> BotResult {
+    let chat_id = note.meta.chat_id;
+    let (card, mut inline_keyboard) = create_note_card(note, config.utc_offset());
+    let snooze_row = SNOOZE_OPTIONS.iter()
+        .map(|&(label, minutes)| {
+            InlineKeyboardButton::new(label, InlineKeyboardButtonKind::CallbackData(Callback::Snooze { note: note.id, minutes }.to_string()))
+        })
+        .collect();
+    inline_keyboard.inline_keyboard.insert(0, snooze_row);
+
+    if let Some(attachment) = &note.attachment {
+        send_attachment(bot, chat_id, attachment).await?;
+    }
+    bot.send_message(chat_id, format!("⏰*Напоминание\\!*\n\n{}", card))
+        .reply_markup(inline_keyboard)
+        .parse_mode(MarkdownV2)
+        .await?;
+
+    Ok(())
+}
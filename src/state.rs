@@ -0,0 +1,65 @@
+use serde::{Deserialize, Serialize};
+use teloxide::dispatching::dialogue::{Dialogue, ErasedStorage};
+
+use crate::{functions::now, storage::NoteId};
+
+pub type NoteDialogue = Dialogue<State, ErasedStorage<State>>;
+
+#[derive(Clone, Default, Debug, PartialEq, Serialize, Deserialize)]
+pub enum State {
+    #[default]
+    Idle,
+    AwaitingNewText {
+        note: NoteId,
+        since: u64,
+    },
+    AwaitingNewTitle {
+        note: NoteId,
+        since: u64,
+    },
+    AwaitingNewTags {
+        note: NoteId,
+        since: u64,
+    },
+    AwaitingAppendix {
+        note: NoteId,
+        since: u64,
+    },
+    AwaitingReminder {
+        note: NoteId,
+        since: u64,
+    },
+}
+
+impl State {
+    pub fn awaiting_new_text(note: NoteId) -> Self {
+        State::AwaitingNewText { note, since: now() }
+    }
+
+    pub fn awaiting_new_title(note: NoteId) -> Self {
+        State::AwaitingNewTitle { note, since: now() }
+    }
+
+    pub fn awaiting_new_tags(note: NoteId) -> Self {
+        State::AwaitingNewTags { note, since: now() }
+    }
+
+    pub fn awaiting_appendix(note: NoteId) -> Self {
+        State::AwaitingAppendix { note, since: now() }
+    }
+
+    pub fn awaiting_reminder(note: NoteId) -> Self {
+        State::AwaitingReminder { note, since: now() }
+    }
+
+    pub fn is_expired(&self, timeout_secs: u64) -> bool {
+        match self {
+            State::Idle => false,
+            State::AwaitingNewText { since, .. }
+            | State::AwaitingNewTitle { since, .. }
+            | State::AwaitingNewTags { since, .. }
+            | State::AwaitingAppendix { since, .. }
+            | State::AwaitingReminder { since, .. } => now().saturating_sub(*since) > timeout_secs,
+        }
+    }
+}
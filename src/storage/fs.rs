@@ -0,0 +1,735 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use serde::{Deserialize, Serialize};
+use teloxide::types::{ChatId, MessageEntity, UserId};
+
+use super::{count_tags, move_tag, parse_tags, Note, NoteId, NoteMeta, NoteStore, Search, StorageError, StoreResult, TagFilter};
+use crate::{functions::now, reminders::Reminder};
+
+const IDS_FILE: &str = "ids.json";
+const NOTE_EXTENSION: &str = "txt";
+
+/// Stores every note of a chat as `<chat_id>/<id>.txt`: a short header with
+/// the tags, the title and the metadata, an empty line and the note text.
+pub struct FsStore {
+    root: PathBuf,
+    ids_lock: Mutex<()>,
+    chat_locks: Mutex<HashMap<ChatId, Arc<Mutex<()>>>>,
+}
+
+/// Per-chat id counter. Before notes were named by id the file also mapped
+/// ids to their `#tag/title.txt` location; that map is only read while
+/// migrating such folders.
+#[derive(Default, Serialize, Deserialize)]
+struct NoteIds {
+    next: NoteId,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    notes: BTreeMap<NoteId, (String, String)>,
+    #[serde(skip)]
+    changed: bool,
+}
+
+impl NoteIds {
+    fn allocate(&mut self) -> NoteId {
+        self.next += 1;
+        self.changed = true;
+        self.next
+    }
+
+    /// The id the folder layout gave to `tag/title`, or a fresh one.
+    fn legacy_id(&mut self, tag: &str, title: &str) -> NoteId {
+        let existing = self.notes.iter()
+            .find(|(_, (note_tag, note_title))| note_tag == tag && note_title == title)
+            .map(|(id, _)| *id);
+
+        match existing {
+            Some(id) => {
+                self.notes.remove(&id);
+                self.changed = true;
+                id
+            },
+            None => self.allocate(),
+        }
+    }
+}
+
+impl FsStore {
+    pub fn new(root: impl AsRef<Path>) -> StoreResult<Self> {
+        let root = root.as_ref().to_path_buf();
+        std::fs::create_dir_all(&root)?;
+
+        let store = FsStore { root, ids_lock: Mutex::new(()), chat_locks: Mutex::new(HashMap::new()) };
+        store.repair_backslash_paths();
+        store.migrate_tag_folders()?;
+
+        Ok(store)
+    }
+
+    fn user_folder(&self, chat_id: ChatId) -> PathBuf {
+        self.root.join(chat_id.to_string())
+    }
+
+    fn note_path(&self, chat_id: ChatId, id: NoteId) -> PathBuf {
+        self.user_folder(chat_id).join(format!("{}.{}", id, NOTE_EXTENSION))
+    }
+
+    fn with_ids<T>(&self, chat_id: ChatId, f: impl FnOnce(&mut NoteIds) -> T) -> StoreResult<T> {
+        let _guard = self.ids_lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let path = self.user_folder(chat_id).join(IDS_FILE);
+
+        let mut ids: NoteIds = if path.is_file() {
+            serde_json::from_str(&std::fs::read_to_string(&path)?)?
+        } else {
+            NoteIds::default()
+        };
+
+        let result = f(&mut ids);
+
+        if ids.changed {
+            std::fs::create_dir_all(self.user_folder(chat_id))?;
+            write_atomically(&path, &serde_json::to_string(&ids)?)?;
+        }
+
+        Ok(result)
+    }
+
+    /// Runs `f` while no other change to the chat's notes is under way, so
+    /// that a read-modify-write does not lose a concurrent one.
+    fn with_chat_lock<T>(&self, chat_id: ChatId, f: impl FnOnce() -> StoreResult<T>) -> StoreResult<T> {
+        let lock = self.chat_locks.lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .entry(chat_id)
+            .or_default()
+            .clone();
+        let _guard = lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        f()
+    }
+
+    /// Rewrites a note with whatever `change` makes of it.
+    fn modify(&self, chat_id: ChatId, id: NoteId, change: impl FnOnce(Note) -> Note) -> StoreResult<()> {
+        self.with_chat_lock(chat_id, || {
+            let note = self.get(chat_id, id)?.ok_or(StorageError::NotFound(id))?;
+            write_note(&self.note_path(chat_id, id), &change(note))?;
+
+            Ok(())
+        })
+    }
+
+    fn read_notes(&self, chat_id: ChatId) -> StoreResult<Vec<Note>> {
+        let folder = self.user_folder(chat_id);
+        if !folder.is_dir() {
+            return Ok(Vec::new());
+        }
+
+        let mut notes = Vec::new();
+        for entry in std::fs::read_dir(folder)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|extension| extension == NOTE_EXTENSION) {
+                notes.extend(read_note(&path, chat_id)?);
+            }
+        }
+        notes.sort_by(|a, b| (&a.tags, &a.title).cmp(&(&b.tags, &b.title)));
+
+        Ok(notes)
+    }
+
+    /// Earlier versions built paths with a hard-coded `\` separator, so on
+    /// Linux every note ended up next to the notes folder as a single file
+    /// named like `Заметки\#tag\title.txt`. Moves such files to where they
+    /// belong and drops the empty look-alike folders.
+    fn repair_backslash_paths(&self) {
+        let root_name = match self.root.file_name().and_then(|name| name.to_str()) {
+            Some(name) => format!("{}\\", name),
+            None => return,
+        };
+        let parent = match self.root.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+
+        let entries = match std::fs::read_dir(parent) {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+
+        for entry in entries.flatten() {
+            let name = entry.file_name();
+            let relative = match name.to_str().and_then(|name| name.strip_prefix(&root_name)) {
+                Some(relative) => relative.to_string(),
+                None => continue,
+            };
+
+            let path = entry.path();
+            if path.is_dir() {
+                if let Err(e) = std::fs::remove_dir(&path) {
+                    log::warn!("cannot remove legacy folder {}: {}", path.display(), e);
+                }
+                continue;
+            }
+
+            let target = relative.split('\\').fold(self.root.clone(), |target, part| target.join(part));
+            if target.exists() {
+                log::warn!("not moving {}: {} already exists", path.display(), target.display());
+                continue;
+            }
+            let moved = match target.parent() {
+                Some(folder) => std::fs::create_dir_all(folder).and_then(|_| std::fs::rename(&path, &target)),
+                None => std::fs::rename(&path, &target),
+            };
+            match moved {
+                Ok(()) => log::info!("moved {} to {}", path.display(), target.display()),
+                Err(e) => log::warn!("cannot move {} to {}: {}", path.display(), target.display(), e),
+            }
+        }
+    }
+
+    /// Notes used to live in one folder per tag as `<chat_id>/#tag/title.txt`.
+    /// Rewrites them as `<chat_id>/<id>.txt`, keeping the ids handed out
+    /// back then.
+    fn migrate_tag_folders(&self) -> StoreResult<()> {
+        for chat in std::fs::read_dir(&self.root)?.flatten() {
+            let chat_id = match chat.file_name().to_str().and_then(|name| name.parse().ok()) {
+                Some(chat_id) if chat.path().is_dir() => ChatId(chat_id),
+                _ => continue,
+            };
+
+            for tag_entry in std::fs::read_dir(chat.path())?.flatten() {
+                let tag_path = tag_entry.path();
+                let tag = tag_entry.file_name();
+                let tag = match tag.to_str() {
+                    Some(tag) if tag.starts_with('#') && tag_path.is_dir() => tag.to_string(),
+                    _ => continue,
+                };
+                let note_tag = match legacy_tag(&tag) {
+                    Some(note_tag) => note_tag,
+                    None => {
+                        log::warn!("not migrating tag folder {}: {} cannot be made a tag", tag_path.display(), tag);
+                        continue;
+                    },
+                };
+
+                for file in std::fs::read_dir(&tag_path)?.flatten() {
+                    let path = file.path();
+                    let title = match path.file_stem().and_then(|stem| stem.to_str()) {
+                        Some(title) if path.extension().is_some_and(|extension| extension == NOTE_EXTENSION) => title.to_string(),
+                        _ => continue,
+                    };
+
+                    let text = std::fs::read_to_string(&path)?;
+                    let modified = file.metadata()?.modified()?
+                        .duration_since(std::time::UNIX_EPOCH)
+                        .map(|d| d.as_secs())
+                        .unwrap_or_default();
+                    let meta = NoteMeta { created_at: modified, updated_at: modified, author: None, chat_id };
+
+                    let id = self.with_ids(chat_id, |ids| ids.legacy_id(&tag, &title))?;
+                    let target = self.note_path(chat_id, id);
+                    write_note(&target, &Note { id, tags: vec![note_tag.clone()], title, text, entities: Vec::new(), attachment: None, reminder: None, meta })?;
+
+                    // The old file goes only once the new one reads back as a note.
+                    if read_note(&target, chat_id)?.is_none() {
+                        std::fs::remove_file(&target)?;
+                        log::warn!("not migrating {}: it does not read back as a note", path.display());
+                        continue;
+                    }
+                    std::fs::remove_file(&path)?;
+                    log::info!("moved {} to {}", path.display(), target.display());
+                }
+
+                if std::fs::remove_dir(&tag_path).is_err() {
+                    log::warn!("tag folder {} still has files that could not be migrated", tag_path.display());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn migrate_shared_notes(&self, owner: ChatId) -> StoreResult<()> {
+        let owner_folder = self.user_folder(owner);
+
+        for entry in std::fs::read_dir(&self.root)?.flatten() {
+            let tag_path = entry.path();
+            let tag = entry.file_name();
+            let tag = match tag.to_str() {
+                Some(tag) if tag.starts_with('#') && tag_path.is_dir() => tag.to_string(),
+                _ => continue,
+            };
+
+            std::fs::create_dir_all(&owner_folder)?;
+            let target_folder = owner_folder.join(&tag);
+            if std::fs::rename(&tag_path, &target_folder).is_ok() {
+                log::info!("moved shared tag {} to chat {}", tag, owner);
+                continue;
+            }
+
+            std::fs::create_dir_all(&target_folder)?;
+            for file in std::fs::read_dir(&tag_path)?.flatten() {
+                let target_file = target_folder.join(file.file_name());
+                if target_file.exists() {
+                    log::warn!("not moving {}: {} already exists", file.path().display(), target_file.display());
+                } else {
+                    std::fs::rename(file.path(), target_file)?;
+                }
+            }
+            if std::fs::remove_dir(&tag_path).is_err() {
+                log::warn!("shared tag {} still has notes that could not be moved", tag);
+            }
+        }
+
+        self.migrate_tag_folders()
+    }
+
+}
+
+impl NoteStore for FsStore {
+    fn create(&self, chat_id: ChatId, note: &Note) -> StoreResult<NoteId> {
+        std::fs::create_dir_all(self.user_folder(chat_id))?;
+        let id = self.with_ids(chat_id, NoteIds::allocate)?;
+        write_note(&self.note_path(chat_id, id), &Note { id, ..note.clone() })?;
+
+        Ok(id)
+    }
+
+    fn get(&self, chat_id: ChatId, id: NoteId) -> StoreResult<Option<Note>> {
+        let path = self.note_path(chat_id, id);
+
+        if !path.is_file() {
+            return Ok(None);
+        }
+
+        read_note(&path, chat_id)
+    }
+
+    fn find(&self, chat_id: ChatId, tag: &str, title: &str) -> StoreResult<Option<Note>> {
+        let notes = self.read_notes(chat_id)?;
+
+        Ok(notes.into_iter().find(|note| note.has_tag(tag) && note.title == title))
+    }
+
+    fn update(&self, chat_id: ChatId, id: NoteId, text: &str, entities: &[MessageEntity]) -> StoreResult<()> {
+        self.modify(chat_id, id, |note| {
+            let meta = NoteMeta { updated_at: now(), ..note.meta };
+            Note { text: text.to_string(), entities: entities.to_vec(), meta, ..note }
+        })
+    }
+
+    fn update_header(&self, chat_id: ChatId, id: NoteId, tags: &[String], title: &str) -> StoreResult<()> {
+        self.modify(chat_id, id, |note| {
+            let meta = NoteMeta { updated_at: now(), ..note.meta };
+            Note { tags: tags.to_vec(), title: title.to_string(), meta, ..note }
+        })
+    }
+
+    fn set_reminder(&self, chat_id: ChatId, id: NoteId, reminder: Option<&Reminder>) -> StoreResult<()> {
+        self.modify(chat_id, id, |note| Note { reminder: reminder.copied(), ..note })
+    }
+
+    fn advance_reminder(&self, chat_id: ChatId, id: NoteId, current: &Reminder, next: Option<&Reminder>) -> StoreResult<bool> {
+        self.with_chat_lock(chat_id, || match self.get(chat_id, id)? {
+            Some(note) if note.reminder.as_ref() == Some(current) => {
+                write_note(&self.note_path(chat_id, id), &Note { reminder: next.copied(), ..note })?;
+                Ok(true)
+            },
+            _ => Ok(false),
+        })
+    }
+
+    fn delete(&self, chat_id: ChatId, id: NoteId) -> StoreResult<()> {
+        self.with_chat_lock(chat_id, || {
+            let path = self.note_path(chat_id, id);
+            if path.is_file() {
+                std::fs::remove_file(path)?;
+            }
+
+            Ok(())
+        })
+    }
+
+    fn list(&self, chat_id: ChatId) -> StoreResult<Vec<Note>> {
+        self.read_notes(chat_id)
+    }
+
+    fn list_by_tags(&self, chat_id: ChatId, tags: &TagFilter) -> StoreResult<Vec<Note>> {
+        let notes = self.read_notes(chat_id)?;
+
+        Ok(notes.into_iter().filter(|note| tags.matches(note)).collect())
+    }
+
+    fn tags(&self, chat_id: ChatId) -> StoreResult<Vec<(String, usize)>> {
+        let notes = self.read_notes(chat_id)?;
+
+        Ok(count_tags(notes.iter().map(Note::tag_line)))
+    }
+
+    fn search(&self, chat_id: ChatId, tags: Option<&TagFilter>, search: &Search) -> StoreResult<Vec<Note>> {
+        let notes = self.read_notes(chat_id)?;
+
+        Ok(notes.into_iter()
+            .filter(|note| tags.is_none_or(|tags| tags.matches(note)))
+            .filter(|note| search.matches(note))
+            .collect())
+    }
+
+    /// Every moved note is written aside first, so a failure there changes
+    /// nothing. If putting one in place fails after that, the notes already
+    /// moved are written back with their old tags; only a failure while doing
+    /// so leaves some notes moved, and that is logged.
+    fn move_tag(&self, chat_id: ChatId, from: &str, to: &str) -> StoreResult<usize> {
+        self.with_chat_lock(chat_id, || {
+            let notes = self.read_notes(chat_id)?;
+            let old_tags: HashMap<NoteId, Vec<String>> = notes.iter().map(|note| (note.id, note.tags.clone())).collect();
+            let moved = move_tag(notes, from, to)?;
+
+            let mut temp_paths = Vec::with_capacity(moved.len());
+            for note in &moved {
+                let temp_path = temp_path(&self.note_path(chat_id, note.id));
+                let written = std::fs::write(&temp_path, note_content(note));
+                temp_paths.push(temp_path);
+                if let Err(error) = written {
+                    remove_files(&temp_paths);
+                    return Err(error.into());
+                }
+            }
+
+            for (done, (note, temp_path)) in moved.iter().zip(&temp_paths).enumerate() {
+                if let Err(error) = std::fs::rename(temp_path, self.note_path(chat_id, note.id)) {
+                    remove_files(&temp_paths[done..]);
+                    for note in &moved[..done] {
+                        let tags = old_tags.get(&note.id).cloned().unwrap_or_default();
+                        if let Err(error) = write_note(&self.note_path(chat_id, note.id), &Note { tags, ..note.clone() }) {
+                            log::error!("cannot move note {} in chat {} back to {}: {}", note.id, chat_id, from, error);
+                        }
+                    }
+                    return Err(error.into());
+                }
+            }
+
+            Ok(moved.len())
+        })
+    }
+
+    fn due_reminders(&self, now: u64) -> StoreResult<Vec<Note>> {
+        let mut notes = Vec::new();
+        for chat_id in self.chats()? {
+            notes.extend(self.read_notes(chat_id)?.into_iter().filter(|note| note.reminder.is_some_and(|reminder| reminder.due() <= now)));
+        }
+
+        Ok(notes)
+    }
+
+    fn chats(&self) -> StoreResult<Vec<ChatId>> {
+        let mut chats = Vec::new();
+        for entry in std::fs::read_dir(&self.root)? {
+            let entry = entry?;
+            if let Some(chat_id) = entry.file_name().to_str().and_then(|name| name.parse().ok()) {
+                if entry.path().is_dir() {
+                    chats.push(ChatId(chat_id));
+                }
+            }
+        }
+
+        Ok(chats)
+    }
+}
+
+/// The tag of a legacy `#tag` folder as [`parse_tags`] takes it. The folder
+/// layout allowed any name after `#`, so spaces and inner `#` become `_`;
+/// `None` if even that is not a tag, like a bare `#`.
+fn legacy_tag(folder: &str) -> Option<String> {
+    let name = folder.strip_prefix('#')?.replace(|c: char| c.is_whitespace() || c == '#', "_");
+
+    match parse_tags(&format!("#{}", name))?.as_slice() {
+        [tag] => Some(tag.clone()),
+        _ => None,
+    }
+}
+
+/// Reads a note file; files without a proper header are skipped.
+fn read_note(path: &Path, chat_id: ChatId) -> StoreResult<Option<Note>> {
+    let id = match path.file_stem().and_then(|stem| stem.to_str()).and_then(|stem| stem.parse().ok()) {
+        Some(id) => id,
+        None => return Ok(None),
+    };
+
+    let content = std::fs::read_to_string(path)?;
+    let note = parse_note(id, chat_id, &content);
+    if note.is_none() {
+        log::warn!("skipping {}: no tags or title header", path.display());
+    }
+
+    Ok(note)
+}
+
+/// Parses the header written by [`write_note`]; only `tags` and `title` are required.
+fn parse_note(id: NoteId, chat_id: ChatId, content: &str) -> Option<Note> {
+    let (header, text) = content.split_once("\n\n")?;
+    let mut tags = None;
+    let mut title = None;
+    let mut entities = Vec::new();
+    let mut attachment = None;
+    let mut reminder = None;
+    let mut meta = NoteMeta { created_at: 0, updated_at: 0, author: None, chat_id };
+
+    for line in header.lines() {
+        let (key, value) = match line.split_once(": ") {
+            Some(pair) => pair,
+            None => continue,
+        };
+        match key {
+            "tags" => tags = parse_tags(value),
+            "title" => title = Some(value.to_string()),
+            "created" => meta.created_at = value.parse().unwrap_or_default(),
+            "updated" => meta.updated_at = value.parse().unwrap_or_default(),
+            "author" => meta.author = value.parse().ok().map(UserId),
+            "chat" => meta.chat_id = value.parse().map(ChatId).unwrap_or(chat_id),
+            "entities" => entities = serde_json::from_str(value).unwrap_or_default(),
+            "attachment" => attachment = serde_json::from_str(value).ok(),
+            "reminder" => reminder = serde_json::from_str(value).ok(),
+            _ => {},
+        }
+    }
+
+    Some(Note { id, tags: tags?, title: title?, text: text.to_string(), entities, attachment, reminder, meta })
+}
+
+fn write_note(path: &Path, note: &Note) -> std::io::Result<()> {
+    write_atomically(path, &note_content(note))
+}
+
+/// The header and the text of a note file.
+fn note_content(note: &Note) -> String {
+    let mut header = format!(
+        "tags: {}\ntitle: {}\ncreated: {}\nupdated: {}\nchat: {}\n",
+        note.tag_line(), note.title, note.meta.created_at, note.meta.updated_at, note.meta.chat_id
+    );
+    if let Some(author) = note.meta.author {
+        header.push_str(&format!("author: {}\n", author));
+    }
+    if !note.entities.is_empty() {
+        header.push_str(&format!("entities: {}\n", serde_json::to_string(&note.entities).unwrap_or_default()));
+    }
+    if let Some(attachment) = &note.attachment {
+        header.push_str(&format!("attachment: {}\n", serde_json::to_string(attachment).unwrap_or_default()));
+    }
+    if let Some(reminder) = &note.reminder {
+        header.push_str(&format!("reminder: {}\n", serde_json::to_string(reminder).unwrap_or_default()));
+    }
+
+    format!("{}\n{}", header, note.text)
+}
+
+/// A temporary file next to `path` that no other write uses, even one from
+/// another process.
+fn temp_path(path: &Path) -> PathBuf {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+
+    path.with_extension(format!("{}-{}.tmp", std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed)))
+}
+
+/// Removes the temporary files of a write that failed.
+fn remove_files(paths: &[PathBuf]) {
+    for path in paths {
+        if let Err(error) = std::fs::remove_file(path) {
+            log::warn!("cannot remove {}: {}", path.display(), error);
+        }
+    }
+}
+
+/// Writes to a temporary file first so that a crash never leaves a half-written file behind.
+fn write_atomically(path: &Path, content: &str) -> std::io::Result<()> {
+    let temp_path = temp_path(path);
+    std::fs::write(&temp_path, content)?;
+
+    std::fs::rename(temp_path, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{test_note as note, Attachment, AttachmentKind};
+
+    fn tags(line: &str) -> TagFilter {
+        TagFilter::parse(line).unwrap()
+    }
+
+    #[test]
+    fn notes_round_trip_through_id_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FsStore::new(dir.path().join("Заметки")).unwrap();
+        let chat_id = ChatId(42);
+
+        let created = Note {
+            attachment: Some(Attachment { kind: AttachmentKind::Photo, file_id: "AgACAgIAAx".to_string(), path: None }),
+            ..note("#игры #вечер", "Игра на вечер", "Хочу поиграть в Скайрим\nили в Ведьмака")
+        };
+        let id = store.create(chat_id, &created).unwrap();
+
+        assert!(dir.path().join("Заметки").join("42").join(format!("{}.txt", id)).is_file());
+        assert_eq!(store.get(chat_id, id).unwrap(), Some(Note { id, ..created.clone() }));
+        assert_eq!(store.find(chat_id, "#вечер", "Игра на вечер").unwrap().unwrap().id, id);
+        assert_eq!(store.list_by_tags(chat_id, &tags("#вечер")).unwrap().len(), 1);
+        assert_eq!(store.tags(chat_id).unwrap(), vec![("#вечер".to_string(), 1), ("#игры".to_string(), 1)]);
+        assert_eq!(store.search(chat_id, None, &Search::Title("вечер".to_string())).unwrap()[0].id, id);
+        assert_eq!(store.search(chat_id, Some(&tags("#игры")), &Search::Phrase("скайрим".to_string())).unwrap()[0].id, id);
+
+        store.update(chat_id, id, "Ведьмак", &[MessageEntity::bold(0, 7)]).unwrap();
+        let updated = store.get(chat_id, id).unwrap().unwrap();
+        assert_eq!(updated.text, "Ведьмак");
+        assert_eq!(updated.entities, vec![MessageEntity::bold(0, 7)]);
+        assert_eq!(updated.meta.author, Some(UserId(7)));
+        assert!(updated.meta.updated_at >= created.meta.created_at);
+
+        let reminder = Reminder::once(2_000_000_000);
+        store.set_reminder(chat_id, id, Some(&reminder)).unwrap();
+        assert!(store.due_reminders(1_999_999_999).unwrap().is_empty());
+        assert_eq!(store.due_reminders(2_000_000_000).unwrap().into_iter().map(|note| (note.id, note.reminder)).collect::<Vec<_>>(), vec![(id, Some(reminder))]);
+        store.set_reminder(chat_id, id, None).unwrap();
+        assert!(store.due_reminders(2_000_000_000).unwrap().is_empty());
+
+        store.set_reminder(chat_id, id, Some(&reminder)).unwrap();
+        assert!(!store.advance_reminder(chat_id, id, &Reminder::once(1_000), None).unwrap());
+        assert!(store.advance_reminder(chat_id, id, &reminder, None).unwrap());
+        assert!(store.due_reminders(2_000_000_000).unwrap().is_empty());
+        assert!(!store.advance_reminder(chat_id, 999, &reminder, None).unwrap());
+
+        store.delete(chat_id, id).unwrap();
+        assert_eq!(store.get(chat_id, id).unwrap(), None);
+        assert!(store.list_by_tags(chat_id, &tags("#игры")).unwrap().is_empty());
+    }
+
+    #[test]
+    fn tag_filters_intersect_and_unite() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FsStore::new(dir.path().join("Заметки")).unwrap();
+        let chat_id = ChatId(42);
+
+        store.create(chat_id, &note("#work #rust", "Borrow checker", "")).unwrap();
+        store.create(chat_id, &note("#work", "Созвон", "")).unwrap();
+        store.create(chat_id, &note("#rust", "Clippy", "")).unwrap();
+
+        assert_eq!(store.list_by_tags(chat_id, &tags("#work #rust")).unwrap().len(), 1);
+        assert_eq!(store.list_by_tags(chat_id, &tags("#work | #rust")).unwrap().len(), 3);
+        assert_eq!(store.list_by_tags(chat_id, &tags("#rust")).unwrap().len(), 2);
+
+        assert_eq!(store.move_tag(chat_id, "#rust", "#work/rust").unwrap(), 2);
+        assert_eq!(store.list_by_tags(chat_id, &tags("#work")).unwrap().len(), 3);
+        assert!(store.list_by_tags(chat_id, &tags("#rust")).unwrap().is_empty());
+        assert!(!dir.path().join("Заметки").join("42").join("1.tmp").exists());
+    }
+
+    #[test]
+    fn ids_survive_restart_and_are_not_reused() {
+        let dir = tempfile::tempdir().unwrap();
+        let chat_id = ChatId(42);
+
+        let store = FsStore::new(dir.path().join("Заметки")).unwrap();
+        let first = store.create(chat_id, &note("#игры", "Скайрим", "RPG")).unwrap();
+        let second = store.create(chat_id, &note("#игры", "Ведьмак", "RPG")).unwrap();
+        store.delete(chat_id, first).unwrap();
+
+        let store = FsStore::new(dir.path().join("Заметки")).unwrap();
+        assert_eq!(store.get(chat_id, second).unwrap().unwrap().title, "Ведьмак");
+        assert!(store.search(chat_id, None, &Search::Phrase("#игры".to_string())).unwrap().is_empty());
+
+        let third = store.create(chat_id, &note("#игры", "Скайрим", "RPG")).unwrap();
+        assert!(third > second);
+    }
+
+    #[test]
+    fn concurrent_changes_to_a_note_are_all_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FsStore::new(dir.path()).unwrap();
+        let chat_id = ChatId(42);
+        let id = store.create(chat_id, &note("#дела", "Список", "")).unwrap();
+
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                for i in 0..50 {
+                    store.update(chat_id, id, &format!("Версия {}", i), &[]).unwrap();
+                }
+            });
+            scope.spawn(|| {
+                for i in 0..50 {
+                    store.set_reminder(chat_id, id, Some(&Reminder::once(2_000_000_000 + i))).unwrap();
+                }
+            });
+        });
+
+        let saved = store.get(chat_id, id).unwrap().unwrap();
+        assert_eq!(saved.text, "Версия 49");
+        assert_eq!(saved.reminder, Some(Reminder::once(2_000_000_049)));
+        assert_eq!(std::fs::read_dir(dir.path().join("42")).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn chats_do_not_see_each_other() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FsStore::new(dir.path().join("Заметки")).unwrap();
+
+        store.create(ChatId(1), &note("#работа", "План", "Созвон")).unwrap();
+
+        assert!(store.list_by_tags(ChatId(2), &tags("#работа")).unwrap().is_empty());
+        assert!(store.search(ChatId(2), None, &Search::Phrase("созвон".to_string())).unwrap().is_empty());
+    }
+
+    #[test]
+    fn tag_folders_are_migrated_keeping_ids() {
+        let dir = tempfile::tempdir().unwrap();
+        let chat_folder = dir.path().join("Заметки").join("42");
+        std::fs::create_dir_all(chat_folder.join("#игры")).unwrap();
+        std::fs::write(chat_folder.join("#игры").join("Скайрим.txt"), "RPG").unwrap();
+        std::fs::write(chat_folder.join("#игры").join("Ведьмак.txt"), "RPG").unwrap();
+        std::fs::write(chat_folder.join(IDS_FILE), r##"{"next":7,"notes":{"7":["#игры","Скайрим"]}}"##).unwrap();
+
+        let store = FsStore::new(dir.path().join("Заметки")).unwrap();
+
+        assert!(!chat_folder.join("#игры").exists());
+        let migrated = store.get(ChatId(42), 7).unwrap().unwrap();
+        assert_eq!(migrated.title, "Скайрим");
+        assert!(migrated.meta.created_at > 0);
+        assert_eq!(migrated.meta.chat_id, ChatId(42));
+        assert_eq!(store.find(ChatId(42), "#игры", "Ведьмак").unwrap().unwrap().id, 8);
+        assert_eq!(std::fs::read_to_string(chat_folder.join(IDS_FILE)).unwrap(), r#"{"next":8}"#);
+    }
+
+    #[test]
+    fn odd_tag_folders_are_migrated_under_a_valid_tag() {
+        let dir = tempfile::tempdir().unwrap();
+        let chat_folder = dir.path().join("Заметки").join("42");
+        for tag in ["#мои игры", "#C#", "#"] {
+            std::fs::create_dir_all(chat_folder.join(tag)).unwrap();
+            std::fs::write(chat_folder.join(tag).join("Заметка.txt"), "Текст").unwrap();
+        }
+
+        let store = FsStore::new(dir.path().join("Заметки")).unwrap();
+
+        assert_eq!(store.find(ChatId(42), "#мои_игры", "Заметка").unwrap().unwrap().text, "Текст");
+        assert_eq!(store.find(ChatId(42), "#C_", "Заметка").unwrap().unwrap().text, "Текст");
+        assert!(!chat_folder.join("#мои игры").exists());
+        assert!(!chat_folder.join("#C#").exists());
+        assert!(chat_folder.join("#").join("Заметка.txt").is_file());
+        assert_eq!(store.list(ChatId(42)).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn backslash_named_files_are_moved_into_place() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("Заметки\\42")).unwrap();
+        std::fs::write(dir.path().join("Заметки\\42\\#игры\\Игра на вечер.txt"), "Скайрим").unwrap();
+
+        let store = FsStore::new(dir.path().join("Заметки")).unwrap();
+
+        assert!(!dir.path().join("Заметки\\42").exists());
+        assert!(!dir.path().join("Заметки\\42\\#игры\\Игра на вечер.txt").exists());
+        assert_eq!(store.find(ChatId(42), "#игры", "Игра на вечер").unwrap().unwrap().text, "Скайрим");
+    }
+}
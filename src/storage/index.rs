@@ -0,0 +1,531 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
+
+use rust_stemmers::{Algorithm, Stemmer};
+use teloxide::types::{ChatId, MessageEntity};
+
+use super::{title_similarity, Note, NoteId, NoteStore, Search, StoreResult, TagFilter};
+use crate::reminders::Reminder;
+
+/// A phrase found in the title counts as much as this many found in the text.
+const TITLE_WEIGHT: usize = 10;
+/// Turns title similarity into a score while keeping three digits of it.
+const SIMILARITY_SCALE: f64 = 1000.0;
+
+/// Splits text into lowercase words and reduces them to their stems, so that
+/// `игры`, `игру` and `игрой` all end up as the same term.
+pub struct Analyzer {
+    russian: Stemmer,
+    english: Stemmer,
+}
+
+impl Default for Analyzer {
+    fn default() -> Self {
+        Analyzer {
+            russian: Stemmer::create(Algorithm::Russian),
+            english: Stemmer::create(Algorithm::English),
+        }
+    }
+}
+
+impl Analyzer {
+    pub fn terms(&self, text: &str) -> Vec<String> {
+        text.split(|c: char| !c.is_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .map(|word| self.stem(word))
+            .collect()
+    }
+
+    /// Whether `word` is one of the query `terms`; the last term matches as a prefix.
+    pub fn matches(&self, word: &str, terms: &[String]) -> bool {
+        let stem = self.stem(word);
+
+        terms.iter().enumerate().any(|(i, term)| {
+            if i + 1 == terms.len() { stem.starts_with(term.as_str()) } else { stem == *term }
+        })
+    }
+
+    /// Whether the words of `text` contain `terms` in this order, the last
+    /// one as a prefix; no terms are never found.
+    pub fn contains(&self, text: &str, terms: &[String]) -> bool {
+        let (last, rest) = match terms.split_last() {
+            Some(split) => split,
+            None => return false,
+        };
+
+        self.terms(text).windows(terms.len()).any(|window| {
+            window[..rest.len()] == *rest && window[rest.len()].starts_with(last.as_str())
+        })
+    }
+
+    fn stem(&self, word: &str) -> String {
+        let word = word.to_lowercase().replace('ё', "е");
+        let stemmer = if word.chars().any(is_cyrillic) { &self.russian } else { &self.english };
+
+        stemmer.stem(&word).into_owned()
+    }
+}
+
+fn is_cyrillic(c: char) -> bool {
+    matches!(c, '\u{0400}'..='\u{04FF}')
+}
+
+/// Term → note → positions of the term among the words of the field.
+#[derive(Default)]
+struct FieldIndex {
+    postings: BTreeMap<String, BTreeMap<NoteId, Vec<usize>>>,
+}
+
+impl FieldIndex {
+    fn insert(&mut self, id: NoteId, terms: &[String]) {
+        for (position, term) in terms.iter().enumerate() {
+            self.postings.entry(term.clone()).or_default().entry(id).or_default().push(position);
+        }
+    }
+
+    fn remove(&mut self, id: NoteId, terms: &[String]) {
+        for term in terms {
+            if let Some(notes) = self.postings.get_mut(term) {
+                notes.remove(&id);
+                if notes.is_empty() {
+                    self.postings.remove(term);
+                }
+            }
+        }
+    }
+
+    /// Positions of `term` per note; the last word of a query is matched as
+    /// a prefix so that unfinished words still find something.
+    fn positions(&self, term: &str, prefix: bool) -> BTreeMap<NoteId, Vec<usize>> {
+        if !prefix {
+            return self.postings.get(term).cloned().unwrap_or_default();
+        }
+
+        let mut merged: BTreeMap<NoteId, Vec<usize>> = BTreeMap::new();
+        for (_, notes) in self.postings.range(term.to_string()..).take_while(|(key, _)| key.starts_with(term)) {
+            for (id, positions) in notes {
+                merged.entry(*id).or_default().extend(positions);
+            }
+        }
+        for positions in merged.values_mut() {
+            positions.sort_unstable();
+        }
+
+        merged
+    }
+
+    /// How many times `terms` follow each other in this order, per note.
+    fn phrase(&self, terms: &[String]) -> BTreeMap<NoteId, usize> {
+        let postings: Vec<_> = terms.iter()
+            .enumerate()
+            .map(|(i, term)| self.positions(term, i + 1 == terms.len()))
+            .collect();
+
+        let (first, rest) = match postings.split_first() {
+            Some(split) => split,
+            None => return BTreeMap::new(),
+        };
+
+        first.iter()
+            .map(|(id, starts)| {
+                let hits = starts.iter().filter(|start| {
+                    rest.iter().enumerate().all(|(i, notes)| {
+                        notes.get(id).is_some_and(|positions| positions.binary_search(&(*start + i + 1)).is_ok())
+                    })
+                }).count();
+                (*id, hits)
+            })
+            .filter(|(_, hits)| *hits > 0)
+            .collect()
+    }
+}
+
+/// Terms a note was indexed with, kept to unindex it without a full scan,
+/// and its title, lowercase, for title and similarity searches.
+struct IndexedNote {
+    title: String,
+    text_terms: Vec<String>,
+}
+
+/// The searches the postings can answer.
+enum IndexSearch<'a> {
+    Words { query: &'a str, title: bool, text: bool },
+    Similar { title: &'a str, threshold: f64 },
+}
+
+#[derive(Default)]
+struct ChatIndex {
+    text: FieldIndex,
+    notes: HashMap<NoteId, IndexedNote>,
+}
+
+impl ChatIndex {
+    fn insert(&mut self, id: NoteId, note: IndexedNote) {
+        self.remove(id);
+        self.text.insert(id, &note.text_terms);
+        self.notes.insert(id, note);
+    }
+
+    fn remove(&mut self, id: NoteId) {
+        if let Some(note) = self.notes.remove(&id) {
+            self.text.remove(id, &note.text_terms);
+        }
+    }
+
+    /// Matching notes with their relevance score. Titles are short, so they
+    /// are scanned for the query as a substring, the way the stores without
+    /// an index match them; the text is matched by word forms. A query
+    /// without a single word finds no text.
+    fn search(&self, search: &IndexSearch, analyzer: &Analyzer) -> HashMap<NoteId, usize> {
+        let (query, title, text) = match *search {
+            IndexSearch::Words { query, title, text } => (query, title, text),
+            IndexSearch::Similar { title, threshold } => {
+                return self.notes.iter()
+                    .map(|(id, note)| (*id, title_similarity(title, &note.title)))
+                    .filter(|(_, similarity)| *similarity >= threshold)
+                    .map(|(id, similarity)| (id, (similarity * SIMILARITY_SCALE) as usize))
+                    .collect();
+            },
+        };
+
+        let mut scores = HashMap::new();
+        if title && !query.trim().is_empty() {
+            for (id, note) in &self.notes {
+                let hits = note.title.matches(query).count();
+                if hits > 0 {
+                    *scores.entry(*id).or_default() += hits * TITLE_WEIGHT;
+                }
+            }
+        }
+
+        let terms = analyzer.terms(query);
+        if text && !terms.is_empty() {
+            for (id, hits) in self.text.phrase(&terms) {
+                *scores.entry(id).or_default() += hits;
+            }
+        }
+
+        scores
+    }
+}
+
+/// Keeps an in-memory inverted index next to another store so that title and
+/// phrase searches don't have to read every note. Titles match as substrings,
+/// as in the other stores; phrases match word forms in order. The index is built when the
+/// store is opened and follows every change made through it.
+///
+/// Search results come best first: title hits, then how often the phrase
+/// occurs in the text, then the most recently updated.
+///
+/// It also keeps when each reminder is due, so that the scheduler reads only
+/// the notes that are.
+pub struct IndexedStore<S> {
+    inner: S,
+    analyzer: Analyzer,
+    chats: RwLock<HashMap<ChatId, ChatIndex>>,
+    reminders: RwLock<HashMap<(ChatId, NoteId), u64>>,
+}
+
+impl<S: NoteStore> IndexedStore<S> {
+    pub fn new(inner: S) -> StoreResult<Self> {
+        let store = IndexedStore {
+            inner,
+            analyzer: Analyzer::default(),
+            chats: RwLock::new(HashMap::new()),
+            reminders: RwLock::new(HashMap::new()),
+        };
+
+        let mut count = 0;
+        for chat_id in store.inner.chats()? {
+            for note in store.inner.list(chat_id)? {
+                store.index(chat_id, &note);
+                store.track_reminder(chat_id, note.id, note.reminder.as_ref());
+                count += 1;
+            }
+        }
+        log::info!("indexed {} notes", count);
+
+        Ok(store)
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, HashMap<ChatId, ChatIndex>> {
+        self.chats.read().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, HashMap<ChatId, ChatIndex>> {
+        self.chats.write().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn index(&self, chat_id: ChatId, note: &Note) {
+        let indexed = IndexedNote {
+            title: note.title.to_lowercase(),
+            text_terms: self.analyzer.terms(&note.text),
+        };
+
+        self.write().entry(chat_id).or_default().insert(note.id, indexed);
+    }
+
+    fn reindex(&self, chat_id: ChatId, id: NoteId) -> StoreResult<()> {
+        match self.inner.get(chat_id, id)? {
+            Some(note) => self.index(chat_id, &note),
+            None => self.unindex(chat_id, id),
+        }
+
+        Ok(())
+    }
+
+    fn unindex(&self, chat_id: ChatId, id: NoteId) {
+        if let Some(index) = self.write().get_mut(&chat_id) {
+            index.remove(id);
+        }
+    }
+
+    fn track_reminder(&self, chat_id: ChatId, id: NoteId, reminder: Option<&Reminder>) {
+        let mut reminders = self.reminders.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+        match reminder {
+            Some(reminder) => reminders.insert((chat_id, id), reminder.due()),
+            None => reminders.remove(&(chat_id, id)),
+        };
+    }
+}
+
+impl<S: NoteStore> NoteStore for IndexedStore<S> {
+    fn create(&self, chat_id: ChatId, note: &Note) -> StoreResult<NoteId> {
+        let id = self.inner.create(chat_id, note)?;
+        self.reindex(chat_id, id)?;
+        self.track_reminder(chat_id, id, note.reminder.as_ref());
+
+        Ok(id)
+    }
+
+    fn get(&self, chat_id: ChatId, id: NoteId) -> StoreResult<Option<Note>> {
+        self.inner.get(chat_id, id)
+    }
+
+    fn find(&self, chat_id: ChatId, tag: &str, title: &str) -> StoreResult<Option<Note>> {
+        self.inner.find(chat_id, tag, title)
+    }
+
+    fn update(&self, chat_id: ChatId, id: NoteId, text: &str, entities: &[MessageEntity]) -> StoreResult<()> {
+        self.inner.update(chat_id, id, text, entities)?;
+
+        self.reindex(chat_id, id)
+    }
+
+    fn update_header(&self, chat_id: ChatId, id: NoteId, tags: &[String], title: &str) -> StoreResult<()> {
+        self.inner.update_header(chat_id, id, tags, title)?;
+
+        self.reindex(chat_id, id)
+    }
+
+    fn set_reminder(&self, chat_id: ChatId, id: NoteId, reminder: Option<&Reminder>) -> StoreResult<()> {
+        self.inner.set_reminder(chat_id, id, reminder)?;
+        self.track_reminder(chat_id, id, reminder);
+
+        Ok(())
+    }
+
+    fn advance_reminder(&self, chat_id: ChatId, id: NoteId, current: &Reminder, next: Option<&Reminder>) -> StoreResult<bool> {
+        let advanced = self.inner.advance_reminder(chat_id, id, current, next)?;
+        if advanced {
+            self.track_reminder(chat_id, id, next);
+        }
+
+        Ok(advanced)
+    }
+
+    fn delete(&self, chat_id: ChatId, id: NoteId) -> StoreResult<()> {
+        self.inner.delete(chat_id, id)?;
+        self.unindex(chat_id, id);
+        self.track_reminder(chat_id, id, None);
+
+        Ok(())
+    }
+
+    fn list(&self, chat_id: ChatId) -> StoreResult<Vec<Note>> {
+        self.inner.list(chat_id)
+    }
+
+    fn list_by_tags(&self, chat_id: ChatId, tags: &TagFilter) -> StoreResult<Vec<Note>> {
+        self.inner.list_by_tags(chat_id, tags)
+    }
+
+    fn tags(&self, chat_id: ChatId) -> StoreResult<Vec<(String, usize)>> {
+        self.inner.tags(chat_id)
+    }
+
+    fn search(&self, chat_id: ChatId, tags: Option<&TagFilter>, search: &Search) -> StoreResult<Vec<Note>> {
+        let search = match search {
+            Search::Title(query) => IndexSearch::Words { query, title: true, text: false },
+            Search::Phrase(query) => IndexSearch::Words { query, title: false, text: true },
+            Search::Any(query) => IndexSearch::Words { query, title: true, text: true },
+            Search::Similar { title, threshold } => IndexSearch::Similar { title, threshold: *threshold },
+            // Regexes and dates can't be answered from the postings.
+            Search::Query(_) => return self.inner.search(chat_id, tags, search),
+        };
+
+        let scores = match self.read().get(&chat_id) {
+            Some(index) => index.search(&search, &self.analyzer),
+            None => return Ok(Vec::new()),
+        };
+
+        let mut ranked = Vec::with_capacity(scores.len());
+        for (id, score) in scores {
+            if let Some(note) = self.inner.get(chat_id, id)? {
+                if tags.is_none_or(|tags| tags.matches(&note)) {
+                    ranked.push((score, note));
+                }
+            }
+        }
+        ranked.sort_by(|(a_score, a), (b_score, b)| {
+            b_score.cmp(a_score).then(b.meta.updated_at.cmp(&a.meta.updated_at)).then(a.id.cmp(&b.id))
+        });
+
+        Ok(ranked.into_iter().map(|(_, note)| note).collect())
+    }
+
+    /// Tags are not indexed, so the index stays as it is.
+    fn move_tag(&self, chat_id: ChatId, from: &str, to: &str) -> StoreResult<usize> {
+        self.inner.move_tag(chat_id, from, to)
+    }
+
+    fn due_reminders(&self, now: u64) -> StoreResult<Vec<Note>> {
+        let due: Vec<_> = self.reminders.read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .iter()
+            .filter(|(_, due)| **due <= now)
+            .map(|(key, _)| *key)
+            .collect();
+
+        let mut notes = Vec::with_capacity(due.len());
+        for (chat_id, id) in due {
+            match self.inner.get(chat_id, id)? {
+                Some(note) => notes.push(note),
+                None => self.track_reminder(chat_id, id, None),
+            }
+        }
+
+        Ok(notes)
+    }
+
+    fn chats(&self) -> StoreResult<Vec<ChatId>> {
+        self.inner.chats()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        query::Query,
+        storage::{test_note as note, FsStore, SqliteStore},
+    };
+
+    fn titles(notes: Vec<Note>) -> Vec<String> {
+        notes.into_iter().map(|note| note.title).collect()
+    }
+
+    #[test]
+    fn phrases_match_word_forms_in_order() {
+        let store = IndexedStore::new(SqliteStore::open(":memory:").unwrap()).unwrap();
+        let chat_id = ChatId(42);
+
+        store.create(chat_id, &note("#игры", "Вечер", "Хочу поиграть в новые игры на выходных")).unwrap();
+        store.create(chat_id, &note("#работа", "Созвон", "Обсудить новую игру с командой")).unwrap();
+
+        let phrase = |query: &str| titles(store.search(chat_id, None, &Search::Phrase(query.to_string())).unwrap());
+        let mut found = phrase("новая игра");
+        found.sort();
+        assert_eq!(found, vec!["Вечер", "Созвон"]);
+        assert_eq!(phrase("игры новые"), Vec::<String>::new());
+        assert_eq!(phrase("на выход"), vec!["Вечер"]);
+        assert_eq!(phrase("Обсудили"), vec!["Созвон"]);
+
+        let tags = TagFilter::parse("#работа").unwrap();
+        assert_eq!(titles(store.search(chat_id, Some(&tags), &Search::Phrase("игра".to_string())).unwrap()), vec!["Созвон"]);
+        assert!(store.search(ChatId(7), None, &Search::Phrase("игра".to_string())).unwrap().is_empty());
+    }
+
+    #[test]
+    fn title_hits_rank_above_frequent_text_hits() {
+        let store = IndexedStore::new(SqliteStore::open(":memory:").unwrap()).unwrap();
+        let chat_id = ChatId(42);
+
+        store.create(chat_id, &note("#кино", "Список", "Дракон")).unwrap();
+        store.create(chat_id, &note("#игры", "Скайрим", "Дракон, ещё дракон и снова драконы")).unwrap();
+        store.create(chat_id, &note("#книги", "Драконы Аэрона", "Фэнтези")).unwrap();
+
+        assert_eq!(
+            titles(store.search(chat_id, None, &Search::Any("дракон".to_string())).unwrap()),
+            vec!["Драконы Аэрона", "Скайрим", "Список"]
+        );
+
+        let similar = Search::Similar { title: "скайрм".to_string(), threshold: 0.75 };
+        assert_eq!(titles(store.search(chat_id, None, &similar).unwrap()), vec!["Скайрим"]);
+
+        assert_eq!(titles(store.search(chat_id, None, &Search::Title("рим".to_string())).unwrap()), vec!["Скайрим"]);
+        assert!(store.search(chat_id, None, &Search::Phrase("???".to_string())).unwrap().is_empty());
+        assert!(store.search(chat_id, None, &Search::Any("!!!".to_string())).unwrap().is_empty());
+    }
+
+    #[test]
+    fn find_words_need_not_be_adjacent() {
+        let store = IndexedStore::new(SqliteStore::open(":memory:").unwrap()).unwrap();
+        let chat_id = ChatId(42);
+
+        store.create(chat_id, &note("#игры", "Вечер", "Новая и очень долгая игра")).unwrap();
+        store.create(chat_id, &note("#работа", "Созвон", "Новая задача")).unwrap();
+
+        let find = |query: &str| titles(store.search(chat_id, None, &Query::parse(query).unwrap().into_search()).unwrap());
+        assert_eq!(find("игра новая"), vec!["Вечер"]);
+        assert_eq!(find("\"новая игра\""), Vec::<String>::new());
+        assert_eq!(find("новая").len(), 2);
+    }
+
+    #[test]
+    fn due_reminders_are_kept_in_memory() {
+        let dir = tempfile::tempdir().unwrap();
+        let chat_id = ChatId(42);
+
+        let first = FsStore::new(dir.path()).unwrap();
+        let early = first.create(chat_id, &Note { reminder: Some(Reminder::once(1_000)), ..note("#дела", "Позвонить", "") }).unwrap();
+
+        let store = IndexedStore::new(FsStore::new(dir.path()).unwrap()).unwrap();
+        let late = store.create(chat_id, &Note { reminder: Some(Reminder::once(2_000)), ..note("#дела", "Купить", "") }).unwrap();
+        let due = |now: u64| store.due_reminders(now).unwrap().into_iter().map(|note| note.id).collect::<Vec<_>>();
+        assert_eq!(due(1_500), vec![early]);
+
+        store.set_reminder(chat_id, early, None).unwrap();
+        assert!(due(1_500).is_empty());
+        store.set_reminder(chat_id, early, Some(&Reminder::once(3_000))).unwrap();
+        assert_eq!(due(2_500), vec![late]);
+
+        store.delete(chat_id, late).unwrap();
+        assert_eq!(due(3_000), vec![early]);
+    }
+
+    #[test]
+    fn index_follows_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let chat_id = ChatId(42);
+
+        let first = FsStore::new(dir.path()).unwrap();
+        let id = first.create(chat_id, &note("#игры", "Скайрим", "Драконы и крики")).unwrap();
+
+        let store = IndexedStore::new(FsStore::new(dir.path()).unwrap()).unwrap();
+        assert_eq!(titles(store.search(chat_id, None, &Search::Any("дракон".to_string())).unwrap()), vec!["Скайрим"]);
+
+        store.update(chat_id, id, "Ведьмаки и чудовища", &[]).unwrap();
+        assert!(store.search(chat_id, None, &Search::Phrase("дракон".to_string())).unwrap().is_empty());
+        assert_eq!(titles(store.search(chat_id, None, &Search::Phrase("чудовище".to_string())).unwrap()), vec!["Скайрим"]);
+
+        store.update_header(chat_id, id, &["#игры".to_string()], "Ведьмак").unwrap();
+        assert!(store.search(chat_id, None, &Search::Title("скайрим".to_string())).unwrap().is_empty());
+        assert_eq!(titles(store.search(chat_id, None, &Search::Title("ведьмак".to_string())).unwrap()), vec!["Ведьмак"]);
+
+        store.delete(chat_id, id).unwrap();
+        assert!(store.search(chat_id, None, &Search::Title("ведьмак".to_string())).unwrap().is_empty());
+    }
+}
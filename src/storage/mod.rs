@@ -0,0 +1,388 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use teloxide::types::{ChatId, MessageEntity, UserId};
+use thiserror::Error;
+
+use crate::{functions::now, query::Query, reminders::Reminder};
+
+mod fs;
+mod index;
+mod sqlite;
+
+pub use fs::FsStore;
+pub use index::{Analyzer, IndexedStore};
+pub use sqlite::SqliteStore;
+
+pub type StoreResult<T> = Result<T, StorageError>;
+
+#[derive(Debug, Error)]
+pub enum StorageError {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("sqlite error: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+    #[error("corrupted note ids file: {0}")]
+    Ids(#[from] serde_json::Error),
+    #[error("note {0} not found")]
+    NotFound(u64),
+    #[error("notes would share a tag and a title: {0:?}")]
+    TitleConflicts(Vec<String>),
+}
+
+pub type NoteId = u64;
+
+/// Separates the levels of a nested tag: `#work/rust/async`.
+pub const TAG_SEPARATOR: char = '/';
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Note {
+    pub id: NoteId,
+    pub tags: Vec<String>,
+    pub title: String,
+    pub text: String,
+    /// Formatting of `text` as Telegram sent it.
+    pub entities: Vec<MessageEntity>,
+    pub attachment: Option<Attachment>,
+    pub reminder: Option<Reminder>,
+    pub meta: NoteMeta,
+}
+
+/// A file sent together with a note. Telegram keeps it and lets the bot
+/// send it again by `file_id`; the bot's own copy, if it saves one, is
+/// there for when the id no longer works.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Attachment {
+    pub kind: AttachmentKind,
+    pub file_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AttachmentKind {
+    Photo,
+    Document,
+    Voice,
+}
+
+/// Who wrote a note, where and when. Timestamps are Unix seconds; notes
+/// saved before they were tracked have 0 there.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NoteMeta {
+    pub created_at: u64,
+    pub updated_at: u64,
+    pub author: Option<UserId>,
+    pub chat_id: ChatId,
+}
+
+impl NoteMeta {
+    pub fn new(chat_id: ChatId, author: Option<UserId>) -> Self {
+        let now = now();
+
+        NoteMeta { created_at: now, updated_at: now, author, chat_id }
+    }
+}
+
+impl Note {
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|note_tag| note_tag == tag)
+    }
+
+    /// Whether the note carries `tag` or any tag nested in it.
+    pub fn has_tag_under(&self, tag: &str) -> bool {
+        self.tags.iter().any(|note_tag| is_under(note_tag, tag))
+    }
+
+    /// Tags as the user writes them: `#work #rust`.
+    pub fn tag_line(&self) -> String {
+        self.tags.join(" ")
+    }
+}
+
+/// Whether `tag` is `parent` itself or nested in it at any depth.
+pub fn is_under(tag: &str, parent: &str) -> bool {
+    tag.strip_prefix(parent).is_some_and(|rest| rest.is_empty() || rest.starts_with(TAG_SEPARATOR))
+}
+
+/// The tag one level up: `#work` for `#work/rust`.
+pub fn parent_tag(tag: &str) -> Option<&str> {
+    tag.rsplit_once(TAG_SEPARATOR).map(|(parent, _)| parent)
+}
+
+/// Splits a line like `#work #rust/async` into tags, dropping repeats.
+/// Returns `None` unless every word is a `#tag` whose levels are not empty
+/// and not `.` or `..`.
+pub fn parse_tags(line: &str) -> Option<Vec<String>> {
+    let mut tags: Vec<String> = Vec::new();
+
+    for word in line.split_whitespace() {
+        let name = word.strip_prefix('#')?;
+        if name.contains('#') || name.split(TAG_SEPARATOR).any(|level| matches!(level, "" | "." | "..")) {
+            return None;
+        }
+        if !tags.iter().any(|tag| tag == word) {
+            tags.push(word.to_string());
+        }
+    }
+
+    if tags.is_empty() {
+        return None;
+    }
+
+    Some(tags)
+}
+
+/// Counts how many of the notes behind `tag_lines` carry each tag or a tag
+/// nested in it, by name. Parents are listed even if no note carries them
+/// directly.
+pub fn count_tags(tag_lines: impl IntoIterator<Item = impl AsRef<str>>) -> Vec<(String, usize)> {
+    let mut counts = std::collections::BTreeMap::new();
+    for line in tag_lines {
+        let mut tags = std::collections::BTreeSet::new();
+        for mut tag in line.as_ref().split_whitespace() {
+            tags.insert(tag);
+            while let Some(parent) = parent_tag(tag) {
+                tags.insert(parent);
+                tag = parent;
+            }
+        }
+        for tag in tags {
+            *counts.entry(tag.to_string()).or_default() += 1;
+        }
+    }
+
+    counts.into_iter().collect()
+}
+
+/// Retags the notes under `from`, nested tags included, so that they are
+/// under `to` instead, and returns only the notes that changed. Fails with
+/// [`StorageError::TitleConflicts`] listing every `#tag title` that would
+/// then belong to two notes.
+pub fn move_tag(notes: Vec<Note>, from: &str, to: &str) -> StoreResult<Vec<Note>> {
+    let mut titles: HashMap<(String, String), usize> = HashMap::new();
+    let mut moved = Vec::new();
+
+    for mut note in notes {
+        let mut tags: Vec<String> = Vec::with_capacity(note.tags.len());
+        for tag in &note.tags {
+            let tag = match tag.strip_prefix(from) {
+                Some(rest) if is_under(tag, from) => format!("{}{}", to, rest),
+                _ => tag.clone(),
+            };
+            if !tags.contains(&tag) {
+                tags.push(tag);
+            }
+        }
+
+        for tag in &tags {
+            *titles.entry((tag.clone(), note.title.clone())).or_default() += 1;
+        }
+        if tags != note.tags {
+            note.tags = tags;
+            moved.push(note);
+        }
+    }
+
+    let mut conflicts: Vec<String> = moved.iter()
+        .flat_map(|note| note.tags.iter().map(move |tag| (tag.clone(), note.title.clone())))
+        .filter(|key| titles[key] > 1)
+        .map(|(tag, title)| format!("{} {}", tag, title))
+        .collect();
+    if !conflicts.is_empty() {
+        conflicts.sort();
+        conflicts.dedup();
+        return Err(StorageError::TitleConflicts(conflicts));
+    }
+
+    Ok(moved)
+}
+
+/// Which tags a note must carry: `#work #rust` asks for both,
+/// `#work | #rust` for at least one of them. Nested tags count too:
+/// `#work` matches a note tagged `#work/rust`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TagFilter {
+    All(Vec<String>),
+    AnyOf(Vec<String>),
+}
+
+impl TagFilter {
+    pub fn parse(line: &str) -> Option<Self> {
+        if line.contains('|') {
+            let tags = line.split('|').map(parse_tags).collect::<Option<Vec<_>>>()?;
+            Some(TagFilter::AnyOf(tags.concat()))
+        } else {
+            parse_tags(line).map(TagFilter::All)
+        }
+    }
+
+    pub fn matches(&self, note: &Note) -> bool {
+        match self {
+            TagFilter::All(tags) => tags.iter().all(|tag| note.has_tag_under(tag)),
+            TagFilter::AnyOf(tags) => tags.iter().any(|tag| note.has_tag_under(tag)),
+        }
+    }
+}
+
+pub enum Search {
+    Title(String),
+    Phrase(String),
+    Any(String),
+    /// Titles at least `threshold` similar to `title`, see [`title_similarity`].
+    Similar { title: String, threshold: f64 },
+    /// A parsed `/find` query, see [`crate::query`].
+    Query(Query),
+}
+
+impl Search {
+    pub fn matches(&self, note: &Note) -> bool {
+        match self {
+            Search::Title(search_str) => note.title.to_lowercase().contains(search_str),
+            Search::Phrase(search_str) => note.text.lines().any(|line| line.to_lowercase().contains(search_str)),
+            Search::Any(search_str) => {
+                Search::Title(search_str.clone()).matches(note) || Search::Phrase(search_str.clone()).matches(note)
+            },
+            Search::Similar { title, threshold } => title_similarity(title, &note.title) >= *threshold,
+            Search::Query(query) => query.matches(note),
+        }
+    }
+}
+
+/// How close `query` is to `title`, from 0 to 1, forgiving typos: the best
+/// match of the query against the whole title or against any run of as many
+/// title words as the query has.
+pub fn title_similarity(query: &str, title: &str) -> f64 {
+    let query = query.trim().to_lowercase();
+    let title = title.to_lowercase();
+    let words: Vec<&str> = title.split_whitespace().collect();
+    let count = query.split_whitespace().count().max(1);
+
+    let mut best = strsim::normalized_damerau_levenshtein(&query, &title);
+    if count <= words.len() {
+        for window in words.windows(count) {
+            best = best.max(strsim::normalized_damerau_levenshtein(&query, &window.join(" ")));
+        }
+    }
+
+    best
+}
+
+pub trait NoteStore: Send + Sync {
+    /// Saves a new note and returns the id assigned to it; `note.id` is ignored.
+    fn create(&self, chat_id: ChatId, note: &Note) -> StoreResult<NoteId>;
+
+    fn get(&self, chat_id: ChatId, id: NoteId) -> StoreResult<Option<Note>>;
+
+    /// Finds the note carrying `tag` among its tags and titled `title`.
+    fn find(&self, chat_id: ChatId, tag: &str, title: &str) -> StoreResult<Option<Note>>;
+
+    /// Replaces the text with its formatting and bumps `updated_at`.
+    fn update(&self, chat_id: ChatId, id: NoteId, text: &str, entities: &[MessageEntity]) -> StoreResult<()>;
+
+    /// Replaces the tags and the title and bumps `updated_at`.
+    fn update_header(&self, chat_id: ChatId, id: NoteId, tags: &[String], title: &str) -> StoreResult<()>;
+
+    /// Sets the reminder, or removes it with `None`; `updated_at` stays as it is.
+    fn set_reminder(&self, chat_id: ChatId, id: NoteId, reminder: Option<&Reminder>) -> StoreResult<()>;
+
+    /// Sets the reminder to `next` only if it is still `current`, so that a
+    /// reminder that went off does not undo a change made meanwhile; returns
+    /// whether it did. A deleted note is not an error.
+    fn advance_reminder(&self, chat_id: ChatId, id: NoteId, current: &Reminder, next: Option<&Reminder>) -> StoreResult<bool>;
+
+    /// The notes of every chat whose reminder is due at `now`.
+    fn due_reminders(&self, now: u64) -> StoreResult<Vec<Note>>;
+
+    fn delete(&self, chat_id: ChatId, id: NoteId) -> StoreResult<()>;
+
+    fn list(&self, chat_id: ChatId) -> StoreResult<Vec<Note>>;
+
+    fn list_by_tags(&self, chat_id: ChatId, tags: &TagFilter) -> StoreResult<Vec<Note>>;
+
+    /// Every tag of the chat, parents of nested tags included, with the
+    /// number of notes under it, by name; see [`count_tags`].
+    fn tags(&self, chat_id: ChatId) -> StoreResult<Vec<(String, usize)>>;
+
+    fn search(&self, chat_id: ChatId, tags: Option<&TagFilter>, search: &Search) -> StoreResult<Vec<Note>>;
+
+    /// Moves every note under `from` to `to`, all or none of them, see
+    /// [`move_tag`]; returns how many notes changed. `updated_at` stays.
+    fn move_tag(&self, chat_id: ChatId, from: &str, to: &str) -> StoreResult<usize>;
+
+    /// Every chat that has at least one note.
+    fn chats(&self) -> StoreResult<Vec<ChatId>>;
+}
+
+/// A note of chat 42 by user 7, created just now, for tests to build on.
+#[cfg(test)]
+pub(crate) fn test_note(tags: &str, title: &str, text: &str) -> Note {
+    Note {
+        id: 0,
+        tags: parse_tags(tags).unwrap(),
+        title: title.to_string(),
+        text: text.to_string(),
+        entities: Vec::new(),
+        attachment: None,
+        reminder: None,
+        meta: NoteMeta::new(ChatId(42), Some(UserId(7))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tag_filters_intersect_and_unite() {
+        let note = Note { id: 1, ..test_note("#work #rust #work", "Borrow checker", "") };
+        assert_eq!(note.tag_line(), "#work #rust");
+
+        assert!(TagFilter::parse("#rust #work").unwrap().matches(&note));
+        assert!(!TagFilter::parse("#rust #go").unwrap().matches(&note));
+        assert!(TagFilter::parse("#go | #rust").unwrap().matches(&note));
+        assert!(!TagFilter::parse("#go | #python").unwrap().matches(&note));
+
+        assert_eq!(parse_tags("#work rust"), None);
+        assert_eq!(TagFilter::parse("#go | "), None);
+    }
+
+    #[test]
+    fn nested_tags_include_their_descendants() {
+        let note = Note { id: 1, ..test_note("#work/rust/async #home", "Tokio", "") };
+
+        assert!(TagFilter::parse("#work").unwrap().matches(&note));
+        assert!(TagFilter::parse("#work/rust #home").unwrap().matches(&note));
+        assert!(!TagFilter::parse("#work/go").unwrap().matches(&note));
+        assert!(!TagFilter::parse("#wor").unwrap().matches(&note));
+
+        assert_eq!(
+            count_tags(["#work/rust #work/go", "#work/rust/async"]),
+            vec![("#work".to_string(), 2), ("#work/go".to_string(), 1), ("#work/rust".to_string(), 2), ("#work/rust/async".to_string(), 1)]
+        );
+
+        let moved = move_tag(vec![note.clone()], "#work/rust", "#rust").unwrap();
+        assert_eq!(moved[0].tags, vec!["#rust/async", "#home"]);
+        assert!(move_tag(vec![note.clone()], "#office", "#rust").unwrap().is_empty());
+
+        let other = Note { id: 2, tags: vec!["#home/tools".to_string()], ..note.clone() };
+        assert_eq!(
+            move_tag(vec![note, other], "#home/tools", "#home").unwrap_err().to_string(),
+            "notes would share a tag and a title: [\"#home Tokio\"]"
+        );
+
+        assert_eq!(parse_tags("#work/"), None);
+        assert_eq!(parse_tags("#work//rust"), None);
+        assert_eq!(parse_tags("#../etc"), None);
+    }
+
+    #[test]
+    fn similar_titles_forgive_typos() {
+        assert!(title_similarity("скайрм", "Скайрим") > 0.85);
+        assert!(title_similarity("вечр", "Игра на вечер") > 0.7);
+        assert!(title_similarity("игра на вечер", "Игра на вечер") == 1.0);
+        assert!(title_similarity("ведьмак", "Скайрим") < 0.3);
+    }
+}
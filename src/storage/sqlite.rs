@@ -0,0 +1,357 @@
+use std::{path::Path, sync::Mutex};
+
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use teloxide::types::{ChatId, MessageEntity, UserId};
+
+use super::{count_tags, move_tag, Note, NoteId, NoteMeta, NoteStore, Search, StorageError, StoreResult, TagFilter};
+use crate::{functions::now, reminders::Reminder};
+
+/// Schema migrations, applied in order and tracked with `PRAGMA user_version`.
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE IF NOT EXISTS notes (
+        chat_id INTEGER NOT NULL,
+        tag TEXT NOT NULL,
+        title TEXT NOT NULL,
+        text TEXT NOT NULL,
+        PRIMARY KEY (chat_id, tag, title)
+    );",
+    "CREATE TABLE notes_with_ids (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        chat_id INTEGER NOT NULL,
+        tag TEXT NOT NULL,
+        title TEXT NOT NULL,
+        text TEXT NOT NULL,
+        UNIQUE (chat_id, tag, title)
+    );
+    INSERT INTO notes_with_ids (chat_id, tag, title, text) SELECT chat_id, tag, title, text FROM notes;
+    DROP TABLE notes;
+    ALTER TABLE notes_with_ids RENAME TO notes;",
+    "CREATE TABLE notes_with_tags (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        chat_id INTEGER NOT NULL,
+        tags TEXT NOT NULL,
+        title TEXT NOT NULL,
+        text TEXT NOT NULL
+    );
+    INSERT INTO notes_with_tags (id, chat_id, tags, title, text) SELECT id, chat_id, tag, title, text FROM notes;
+    DELETE FROM sqlite_sequence WHERE name = 'notes_with_tags';
+    INSERT INTO sqlite_sequence (name, seq) SELECT 'notes_with_tags', seq FROM sqlite_sequence WHERE name = 'notes';
+    DROP TABLE notes;
+    ALTER TABLE notes_with_tags RENAME TO notes;
+    CREATE INDEX notes_by_chat ON notes (chat_id);",
+    "ALTER TABLE notes ADD COLUMN created_at INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE notes ADD COLUMN updated_at INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE notes ADD COLUMN author INTEGER;",
+    "ALTER TABLE notes ADD COLUMN entities TEXT NOT NULL DEFAULT '[]';",
+    "ALTER TABLE notes ADD COLUMN attachment TEXT;",
+    "ALTER TABLE notes ADD COLUMN reminder TEXT;",
+];
+
+const NOTE_COLUMNS: &str = "id, tags, title, text, created_at, updated_at, author, chat_id, entities, attachment, reminder";
+
+pub struct SqliteStore {
+    connection: Mutex<Connection>,
+}
+
+impl SqliteStore {
+    pub fn open(path: impl AsRef<Path>) -> StoreResult<Self> {
+        let mut connection = Connection::open(path)?;
+
+        let version: usize = connection.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            let transaction = connection.transaction()?;
+            transaction.execute_batch(migration)?;
+            transaction.pragma_update(None, "user_version", i + 1)?;
+            transaction.commit()?;
+        }
+
+        Ok(SqliteStore { connection: Mutex::new(connection) })
+    }
+
+    fn connection(&self) -> std::sync::MutexGuard<'_, Connection> {
+        self.connection.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn read_note(row: &Row) -> rusqlite::Result<Note> {
+    Ok(Note {
+        id: row.get(0)?,
+        tags: row.get::<_, String>(1)?.split_whitespace().map(str::to_string).collect(),
+        title: row.get(2)?,
+        text: row.get(3)?,
+        // Formatting is not worth failing over: a note reads fine without it.
+        entities: serde_json::from_str(&row.get::<_, String>(8)?).unwrap_or_default(),
+        attachment: row.get::<_, Option<String>>(9)?.and_then(|attachment| serde_json::from_str(&attachment).ok()),
+        reminder: row.get::<_, Option<String>>(10)?.and_then(|reminder| serde_json::from_str(&reminder).ok()),
+        meta: NoteMeta {
+            created_at: row.get(4)?,
+            updated_at: row.get(5)?,
+            author: row.get::<_, Option<u64>>(6)?.map(UserId),
+            chat_id: ChatId(row.get(7)?),
+        },
+    })
+}
+
+fn entities_json(entities: &[MessageEntity]) -> String {
+    serde_json::to_string(entities).unwrap_or_else(|_| "[]".to_string())
+}
+
+impl NoteStore for SqliteStore {
+    fn create(&self, chat_id: ChatId, note: &Note) -> StoreResult<NoteId> {
+        let connection = self.connection();
+        connection.execute(
+            "INSERT INTO notes (chat_id, tags, title, text, created_at, updated_at, author, entities, attachment, reminder) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            params![
+                chat_id.0, note.tag_line(), note.title, note.text,
+                note.meta.created_at, note.meta.updated_at, note.meta.author.map(|author| author.0),
+                entities_json(&note.entities),
+                note.attachment.as_ref().and_then(|attachment| serde_json::to_string(attachment).ok()),
+                note.reminder.as_ref().and_then(|reminder| serde_json::to_string(reminder).ok())
+            ],
+        )?;
+
+        Ok(connection.last_insert_rowid() as NoteId)
+    }
+
+    fn get(&self, chat_id: ChatId, id: NoteId) -> StoreResult<Option<Note>> {
+        let note = self.connection().query_row(
+            &format!("SELECT {} FROM notes WHERE chat_id = ?1 AND id = ?2", NOTE_COLUMNS),
+            params![chat_id.0, id],
+            read_note,
+        ).optional()?;
+
+        Ok(note)
+    }
+
+    fn find(&self, chat_id: ChatId, tag: &str, title: &str) -> StoreResult<Option<Note>> {
+        let connection = self.connection();
+        let mut statement = connection.prepare(
+            &format!("SELECT {} FROM notes WHERE chat_id = ?1 AND title = ?2", NOTE_COLUMNS)
+        )?;
+        let notes: Vec<Note> = statement.query_map(params![chat_id.0, title], read_note)?.collect::<Result<_, _>>()?;
+
+        Ok(notes.into_iter().find(|note| note.has_tag(tag)))
+    }
+
+    fn update(&self, chat_id: ChatId, id: NoteId, text: &str, entities: &[MessageEntity]) -> StoreResult<()> {
+        let updated = self.connection().execute(
+            "UPDATE notes SET text = ?3, entities = ?4, updated_at = ?5 WHERE chat_id = ?1 AND id = ?2",
+            params![chat_id.0, id, text, entities_json(entities), now()],
+        )?;
+
+        if updated == 0 {
+            return Err(StorageError::NotFound(id));
+        }
+
+        Ok(())
+    }
+
+    fn update_header(&self, chat_id: ChatId, id: NoteId, tags: &[String], title: &str) -> StoreResult<()> {
+        let updated = self.connection().execute(
+            "UPDATE notes SET tags = ?3, title = ?4, updated_at = ?5 WHERE chat_id = ?1 AND id = ?2",
+            params![chat_id.0, id, tags.join(" "), title, now()],
+        )?;
+
+        if updated == 0 {
+            return Err(StorageError::NotFound(id));
+        }
+
+        Ok(())
+    }
+
+    fn set_reminder(&self, chat_id: ChatId, id: NoteId, reminder: Option<&Reminder>) -> StoreResult<()> {
+        let updated = self.connection().execute(
+            "UPDATE notes SET reminder = ?3 WHERE chat_id = ?1 AND id = ?2",
+            params![chat_id.0, id, reminder.and_then(|reminder| serde_json::to_string(reminder).ok())],
+        )?;
+
+        if updated == 0 {
+            return Err(StorageError::NotFound(id));
+        }
+
+        Ok(())
+    }
+
+    fn advance_reminder(&self, chat_id: ChatId, id: NoteId, current: &Reminder, next: Option<&Reminder>) -> StoreResult<bool> {
+        let connection = self.connection();
+        let stored: Option<Option<String>> = connection.query_row(
+            "SELECT reminder FROM notes WHERE chat_id = ?1 AND id = ?2",
+            params![chat_id.0, id],
+            |row| row.get(0),
+        ).optional()?;
+
+        let stored = stored.flatten().and_then(|json| serde_json::from_str::<Reminder>(&json).ok());
+        if stored.as_ref() != Some(current) {
+            return Ok(false);
+        }
+        connection.execute(
+            "UPDATE notes SET reminder = ?3 WHERE chat_id = ?1 AND id = ?2",
+            params![chat_id.0, id, next.and_then(|reminder| serde_json::to_string(reminder).ok())],
+        )?;
+
+        Ok(true)
+    }
+
+    fn delete(&self, chat_id: ChatId, id: NoteId) -> StoreResult<()> {
+        self.connection().execute(
+            "DELETE FROM notes WHERE chat_id = ?1 AND id = ?2",
+            params![chat_id.0, id],
+        )?;
+
+        Ok(())
+    }
+
+    fn list(&self, chat_id: ChatId) -> StoreResult<Vec<Note>> {
+        let connection = self.connection();
+        let mut statement = connection.prepare(
+            &format!("SELECT {} FROM notes WHERE chat_id = ?1 ORDER BY tags, title", NOTE_COLUMNS)
+        )?;
+        let notes = statement.query_map(params![chat_id.0], read_note)?.collect::<Result<_, _>>()?;
+
+        Ok(notes)
+    }
+
+    fn list_by_tags(&self, chat_id: ChatId, tags: &TagFilter) -> StoreResult<Vec<Note>> {
+        let notes = self.list(chat_id)?;
+
+        Ok(notes.into_iter().filter(|note| tags.matches(note)).collect())
+    }
+
+    fn tags(&self, chat_id: ChatId) -> StoreResult<Vec<(String, usize)>> {
+        let connection = self.connection();
+        let mut statement = connection.prepare(
+            "SELECT tags FROM notes WHERE chat_id = ?1"
+        )?;
+        let tag_lines: Vec<String> = statement.query_map(params![chat_id.0], |row| row.get(0))?.collect::<Result<_, _>>()?;
+
+        Ok(count_tags(tag_lines))
+    }
+
+    fn search(&self, chat_id: ChatId, tags: Option<&TagFilter>, search: &Search) -> StoreResult<Vec<Note>> {
+        let notes = self.list(chat_id)?;
+
+        Ok(notes.into_iter()
+            .filter(|note| tags.is_none_or(|tags| tags.matches(note)))
+            .filter(|note| search.matches(note))
+            .collect())
+    }
+
+    fn move_tag(&self, chat_id: ChatId, from: &str, to: &str) -> StoreResult<usize> {
+        let mut connection = self.connection();
+        let transaction = connection.transaction()?;
+
+        let notes: Vec<Note> = {
+            let mut statement = transaction.prepare(&format!("SELECT {} FROM notes WHERE chat_id = ?1", NOTE_COLUMNS))?;
+            let notes = statement.query_map(params![chat_id.0], read_note)?.collect::<Result<_, _>>()?;
+            notes
+        };
+        let moved = move_tag(notes, from, to)?;
+        for note in &moved {
+            transaction.execute(
+                "UPDATE notes SET tags = ?3 WHERE chat_id = ?1 AND id = ?2",
+                params![chat_id.0, note.id, note.tag_line()],
+            )?;
+        }
+        transaction.commit()?;
+
+        Ok(moved.len())
+    }
+
+    fn due_reminders(&self, now: u64) -> StoreResult<Vec<Note>> {
+        let connection = self.connection();
+        let mut statement = connection.prepare(
+            &format!("SELECT {} FROM notes WHERE reminder IS NOT NULL", NOTE_COLUMNS)
+        )?;
+        let notes: Vec<Note> = statement.query_map([], read_note)?.collect::<Result<_, _>>()?;
+
+        Ok(notes.into_iter().filter(|note| note.reminder.is_some_and(|reminder| reminder.due() <= now)).collect())
+    }
+
+    fn chats(&self) -> StoreResult<Vec<ChatId>> {
+        let connection = self.connection();
+        let mut statement = connection.prepare("SELECT DISTINCT chat_id FROM notes")?;
+        let chats = statement.query_map([], |row| row.get(0).map(ChatId))?.collect::<Result<_, _>>()?;
+
+        Ok(chats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{test_note as note, Attachment, AttachmentKind};
+
+    #[test]
+    fn notes_round_trip_by_id() {
+        let store = SqliteStore::open(":memory:").unwrap();
+        let chat_id = ChatId(42);
+
+        let voice = Attachment { kind: AttachmentKind::Voice, file_id: "AwACAgIAAx".to_string(), path: Some("Вложения/42/voice.oga".into()) };
+        let id = store.create(chat_id, &Note {
+            attachment: Some(voice.clone()),
+            ..note("#игры #вечер", "Игра на вечер", "Хочу поиграть в Скайрим")
+        }).unwrap();
+
+        assert_eq!(store.find(chat_id, "#вечер", "Игра на вечер").unwrap().unwrap().id, id);
+        let saved = store.get(chat_id, id).unwrap().unwrap();
+        assert_eq!(saved.tags, vec!["#игры", "#вечер"]);
+        assert_eq!(saved.meta.author, Some(UserId(7)));
+        assert_eq!(saved.attachment, Some(voice));
+        assert!(saved.meta.created_at > 0);
+        assert_eq!(store.tags(chat_id).unwrap(), vec![("#вечер".to_string(), 1), ("#игры".to_string(), 1)]);
+        assert_eq!(store.list_by_tags(chat_id, &TagFilter::parse("#кино | #вечер").unwrap()).unwrap().len(), 1);
+        assert_eq!(store.search(chat_id, None, &Search::Phrase("скайрим".to_string())).unwrap()[0].id, id);
+        assert_eq!(store.get(ChatId(7), id).unwrap(), None);
+
+        store.update(chat_id, id, "Ведьмак", &[MessageEntity::bold(0, 7)]).unwrap();
+        assert_eq!(store.get(chat_id, id).unwrap().unwrap().text, "Ведьмак");
+        assert_eq!(store.get(chat_id, id).unwrap().unwrap().entities, vec![MessageEntity::bold(0, 7)]);
+        store.update_header(chat_id, id, &["#игры".to_string(), "#вечер".to_string()], "Ведьмак на вечер").unwrap();
+        assert_eq!(store.find(chat_id, "#вечер", "Ведьмак на вечер").unwrap().unwrap().text, "Ведьмак");
+        store.update_header(chat_id, id, &["#игры".to_string(), "#вечер".to_string()], "Игра на вечер").unwrap();
+
+        let other = store.create(chat_id, &note("#кино", "Игра на вечер", "Игра престолов")).unwrap();
+        assert!(matches!(store.move_tag(chat_id, "#кино", "#вечер"), Err(StorageError::TitleConflicts(_))));
+        assert_eq!(store.get(chat_id, other).unwrap().unwrap().tags, vec!["#кино"]);
+        assert_eq!(store.move_tag(chat_id, "#игры", "#хобби/игры").unwrap(), 1);
+        assert_eq!(store.get(chat_id, id).unwrap().unwrap().tags, vec!["#хобби/игры", "#вечер"]);
+
+        let reminder = Reminder::once(2_000_000_000);
+        store.set_reminder(chat_id, id, Some(&reminder)).unwrap();
+        assert!(store.due_reminders(1_999_999_999).unwrap().is_empty());
+        assert_eq!(store.due_reminders(2_000_000_000).unwrap().into_iter().map(|note| (note.id, note.reminder)).collect::<Vec<_>>(), vec![(id, Some(reminder))]);
+        store.set_reminder(chat_id, id, None).unwrap();
+        assert!(store.due_reminders(2_000_000_000).unwrap().is_empty());
+
+        store.set_reminder(chat_id, id, Some(&reminder)).unwrap();
+        assert!(!store.advance_reminder(chat_id, id, &Reminder::once(1_000), None).unwrap());
+        assert!(store.advance_reminder(chat_id, id, &reminder, None).unwrap());
+        assert!(store.due_reminders(2_000_000_000).unwrap().is_empty());
+        assert!(!store.advance_reminder(chat_id, 999, &reminder, None).unwrap());
+
+        store.delete(chat_id, id).unwrap();
+        assert_eq!(store.get(chat_id, id).unwrap(), None);
+    }
+
+    #[test]
+    fn existing_notes_get_ids_on_migration() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.db");
+
+        let connection = Connection::open(&path).unwrap();
+        connection.execute_batch(MIGRATIONS[0]).unwrap();
+        connection.execute(
+            "INSERT INTO notes (chat_id, tag, title, text) VALUES (42, '#игры', 'Скайрим', 'RPG')",
+            [],
+        ).unwrap();
+        drop(connection);
+
+        let store = SqliteStore::open(path).unwrap();
+        let migrated = store.find(ChatId(42), "#игры", "Скайрим").unwrap().unwrap();
+
+        assert_eq!(migrated.text, "RPG");
+        assert_eq!(migrated.meta.created_at, 0);
+        store.delete(ChatId(42), migrated.id).unwrap();
+        assert!(store.create(ChatId(42), &note("#игры", "Ведьмак", "RPG")).unwrap() > migrated.id);
+    }
+}